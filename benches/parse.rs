@@ -0,0 +1,40 @@
+//! Compares reading `data/bulk_2.csv` through serde with reading it through
+//! [Transaction::from_byte_record], in rows per second.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+use transactions::Transaction;
+
+const INPUT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/data/bulk_2.csv");
+
+fn reader(input: &[u8]) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new().flexible(true).from_reader(input)
+}
+
+fn parse(c: &mut Criterion) {
+    let input = std::fs::read(INPUT).unwrap();
+    let rows = reader(&input).byte_records().count() as u64;
+    let mut group = c.benchmark_group("parse bulk_2.csv");
+    group.throughput(Throughput::Elements(rows));
+
+    group.bench_function("serde", |b| {
+        b.iter(|| {
+            for transaction in reader(&input).deserialize::<Transaction>() {
+                black_box(transaction.ok());
+            }
+        })
+    });
+    group.bench_function("byte record", |b| {
+        b.iter(|| {
+            let mut rdr = reader(&input);
+            let mut record = csv::ByteRecord::new();
+            while rdr.read_byte_record(&mut record).unwrap() {
+                black_box(Transaction::from_byte_record(&record).ok());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
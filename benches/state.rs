@@ -0,0 +1,211 @@
+//! Measures [State::transact] for each transaction type, and how the cost of finding the
+//! transaction a dispute refers to grows with the number of transactions recorded.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use transactions::{ClientId, State, Transaction, TxId, MAIN_ACCOUNT};
+
+/// How many rows of each type are applied per iteration. Every row is for its own client, so a
+/// chargeback never finds its client already locked.
+const ROWS: TxId = 10_000;
+
+/// How many transactions are recorded before disputes are looked up.
+const INDEX_SIZES: [TxId; 4] = [1_000, 10_000, 100_000, 1_000_000];
+
+fn deposit(tx: TxId) -> Transaction {
+    Transaction::Deposit {
+        client: client(tx),
+        account: MAIN_ACCOUNT,
+        tx,
+        amount: 10.0,
+        currency: None,
+        timestamp: None,
+    }
+}
+
+fn client(tx: TxId) -> ClientId {
+    (tx % ROWS) as ClientId
+}
+
+/// Rows for transactions `0..ROWS`, each made by `row` from the transaction id.
+fn rows(row: impl Fn(TxId) -> Transaction) -> Vec<Transaction> {
+    (0..ROWS).map(row).collect()
+}
+
+/// A state which has already applied `rows`.
+fn state_after(rows: Vec<Transaction>) -> State {
+    let mut state = State::default();
+    for row in rows {
+        state.transact(row);
+    }
+    state
+}
+
+fn transact(c: &mut Criterion) {
+    let deposited = state_after(rows(deposit));
+    let mut disputed = deposited.clone();
+    for tx in 0..ROWS {
+        disputed.transact(Transaction::Dispute {
+            client: client(tx),
+            account: MAIN_ACCOUNT,
+            tx,
+            timestamp: None,
+        });
+    }
+    let authorized = state_after(rows(|tx| Transaction::Authorize {
+        client: client(tx),
+        account: MAIN_ACCOUNT,
+        tx,
+        amount: 10.0,
+        currency: None,
+        timestamp: None,
+    }));
+
+    let cases: Vec<(&str, &State, Vec<Transaction>)> = vec![
+        ("deposit", &deposited, rows(|tx| deposit(tx + ROWS))),
+        (
+            "withdrawal",
+            &deposited,
+            rows(|tx| Transaction::Withdrawal {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx: tx + ROWS,
+                amount: 1.0,
+                currency: None,
+                timestamp: None,
+            }),
+        ),
+        (
+            "dispute",
+            &deposited,
+            rows(|tx| Transaction::Dispute {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx,
+                timestamp: None,
+            }),
+        ),
+        (
+            "resolve",
+            &disputed,
+            rows(|tx| Transaction::Resolve {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx,
+                timestamp: None,
+            }),
+        ),
+        (
+            "chargeback",
+            &disputed,
+            rows(|tx| Transaction::Chargeback {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx,
+                timestamp: None,
+            }),
+        ),
+        (
+            "authorize",
+            &deposited,
+            rows(|tx| Transaction::Authorize {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx: tx + ROWS,
+                amount: 1.0,
+                currency: None,
+                timestamp: None,
+            }),
+        ),
+        (
+            "capture",
+            &authorized,
+            rows(|tx| Transaction::Capture {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx,
+                amount: None,
+                timestamp: None,
+            }),
+        ),
+        (
+            "void",
+            &authorized,
+            rows(|tx| Transaction::Void {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx,
+                timestamp: None,
+            }),
+        ),
+        (
+            "refund",
+            &deposited,
+            rows(|tx| Transaction::Refund {
+                client: client(tx),
+                account: MAIN_ACCOUNT,
+                tx: tx + ROWS,
+                amount: 1.0,
+                currency: None,
+                original: tx,
+                timestamp: None,
+            }),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("transact");
+    group.throughput(Throughput::Elements(ROWS.into()));
+    for (kind, state, rows) in cases {
+        group.bench_function(kind, |b| {
+            // the rows change the state, so every iteration starts from a fresh copy
+            b.iter_batched(
+                || (state.clone(), rows.clone()),
+                |(mut state, rows)| {
+                    for row in rows {
+                        state.transact(row);
+                    }
+                    state
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn dispute_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispute lookup");
+    for size in INDEX_SIZES {
+        let mut state = State::default();
+        for tx in 0..size {
+            state.transact(deposit(tx));
+        }
+        // hops around the index rather than walking it in order, so each lookup is a fresh one
+        let mut tx: TxId = 0;
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, &size| {
+            b.iter(|| {
+                tx = tx.wrapping_mul(1_664_525).wrapping_add(1_013_904_223) % size;
+                // resolving straight away leaves the balances as they were for the next lookup
+                for row in [
+                    Transaction::Dispute {
+                        client: client(tx),
+                        account: MAIN_ACCOUNT,
+                        tx,
+                        timestamp: None,
+                    },
+                    Transaction::Resolve {
+                        client: client(tx),
+                        account: MAIN_ACCOUNT,
+                        tx,
+                        timestamp: None,
+                    },
+                ] {
+                    state.transact(row);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, transact, dispute_lookup);
+criterion_main!(benches);
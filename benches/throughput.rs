@@ -0,0 +1,94 @@
+//! Runs the `bin` binary end to end on generated inputs of 1M and 10M rows. The inputs are
+//! written to cargo's temporary directory the first time, and reused after that. The 10M row
+//! input is a couple of hundred megabytes; `cargo bench --bench throughput -- 1M` skips it.
+//! Each input is run both with the usual sequential loop and with `--pipeline`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+const SIZES: [(&str, u64); 2] = [("1M", 1_000_000), ("10M", 10_000_000)];
+
+const MODES: [(&str, &[&str]); 2] = [("sequential", &[]), ("pipelined", &["--pipeline"])];
+
+/// How many clients the generated rows are spread over. It isn't a multiple of 100, so the same
+/// client's row one round of clients back falls on a different slot of [generate]'s pattern.
+const CLIENTS: u64 = 9_973;
+
+/// Writes `rows` rows that look like a busy day: mostly deposits and withdrawals, with the
+/// occasional dispute, most of which are resolved and some charged back.
+fn generate(rows: u64, path: &PathBuf) -> io::Result<()> {
+    let partial = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    writeln!(out, "type,client,tx,amount")?;
+    for tx in 0..rows {
+        let client = tx % CLIENTS;
+        // a dispute in slots 95 to 97 refers to the same client's row one round of clients back,
+        // which is in slots 22 to 24, all deposits. A resolution or chargeback refers to the
+        // deposit disputed three rows before it, so it is for that deposit's client
+        let disputed = |row: u64| row - CLIENTS;
+        match (tx % 100, tx >= CLIENTS + 3) {
+            (60..=94, _) => writeln!(out, "withdrawal,{},{},{}", client, tx, tx % 50)?,
+            (95..=97, true) => writeln!(out, "dispute,{},{}", client, disputed(tx))?,
+            (98, true) => {
+                let original = disputed(tx - 3);
+                writeln!(out, "resolve,{},{}", original % CLIENTS, original)?
+            }
+            (99, true) => {
+                let original = disputed(tx - 3);
+                writeln!(out, "chargeback,{},{}", original % CLIENTS, original)?
+            }
+            // including the first rounds of clients, which have nothing to dispute yet
+            _ => writeln!(
+                out,
+                "deposit,{},{},{}.{:04}",
+                client,
+                tx,
+                tx % 500,
+                tx % 10_000
+            )?,
+        }
+    }
+    out.into_inner()?.sync_all()?;
+    fs::rename(partial, path)
+}
+
+fn input(name: &str, rows: u64) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("rows-{}.csv", name));
+    if !path.exists() {
+        generate(rows, &path).unwrap();
+    }
+    path
+}
+
+fn bin(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bin");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(30));
+    for (name, rows) in SIZES {
+        group.throughput(Throughput::Elements(rows));
+        for (mode, flags) in MODES {
+            group.bench_function(BenchmarkId::new(mode, name), |b| {
+                let path = input(name, rows);
+                b.iter(|| {
+                    let status = Command::new(env!("CARGO_BIN_EXE_bin"))
+                        .args(flags)
+                        .arg(&path)
+                        .stdout(Stdio::null())
+                        .status()
+                        .unwrap();
+                    assert!(status.success());
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bin);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the gRPC service is generated from its schema, using a bundled protoc
+    #[cfg(feature = "grpc")]
+    {
+        let mut config = tonic_build::Config::new();
+        config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+        tonic_build::configure().compile_protos_with_config(
+            config,
+            &["proto/transactions.proto"],
+            &["proto"],
+        )?;
+    }
+    Ok(())
+}
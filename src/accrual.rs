@@ -0,0 +1,83 @@
+use crate::Date;
+use std::str::FromStr;
+
+/// How the fraction of a year between two accrual dates is counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DayCount {
+    /// Actual days elapsed over a 365 day year.
+    #[default]
+    Actual365,
+    /// Actual days elapsed over a 360 day year.
+    Actual360,
+    /// Every month has 30 days and every year has 360 (the US bond basis).
+    Thirty360,
+}
+
+impl DayCount {
+    /// The fraction of a year from `start` to `end`.
+    pub fn year_fraction(&self, start: Date, end: Date) -> f64 {
+        match self {
+            DayCount::Actual365 => end.days_since(start) as f64 / 365.,
+            DayCount::Actual360 => end.days_since(start) as f64 / 360.,
+            DayCount::Thirty360 => {
+                let start_day = i64::from(start.day()).min(30);
+                let mut end_day = i64::from(end.day());
+                if start_day == 30 {
+                    end_day = end_day.min(30);
+                }
+                let days = 360 * (i64::from(end.year()) - i64::from(start.year()))
+                    + 30 * (i64::from(end.month()) - i64::from(start.month()))
+                    + (end_day - start_day);
+                days as f64 / 360.
+            }
+        }
+    }
+}
+
+impl FromStr for DayCount {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "act/365" => Ok(DayCount::Actual365),
+            "act/360" => Ok(DayCount::Actual360),
+            "30/360" => Ok(DayCount::Thirty360),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Rates used when accruing interest and fees on client balances. Interest rates are annual, and
+/// are scaled down to the length of each accrual period using the [DayCount] convention. The
+/// default configuration accrues nothing.
+#[derive(Debug, Clone, Default)]
+pub struct AccrualConfig {
+    /// Annual interest paid to clients on positive available balances.
+    pub credit_rate: f64,
+    /// Annual interest charged to clients on negative available balances.
+    pub overdraft_rate: f64,
+    /// A flat fee charged on every balance each accrual period.
+    pub period_fee: f64,
+    pub day_count: DayCount,
+}
+
+impl AccrualConfig {
+    /// The interest accrued on `balance` over `year_fraction` of a year. Positive results are paid
+    /// to the client and negative results are charged to them.
+    pub fn interest(&self, balance: f64, year_fraction: f64) -> f64 {
+        let rate = if balance < 0. {
+            self.overdraft_rate
+        } else {
+            self.credit_rate
+        };
+        crate::fx::round(balance * rate * year_fraction)
+    }
+}
+
+#[test]
+fn test_year_fraction() {
+    let date = |raw: &str| raw.parse::<Date>().unwrap();
+    let (start, end) = (date("2024-01-31"), date("2024-03-01"));
+    assert_eq!(DayCount::Actual365.year_fraction(start, end), 30. / 365.);
+    assert_eq!(DayCount::Actual360.year_fraction(start, end), 30. / 360.);
+    assert_eq!(DayCount::Thirty360.year_fraction(start, end), 31. / 360.);
+}
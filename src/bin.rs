@@ -1,44 +1,488 @@
 use csv::ReaderBuilder;
-use std::{env, fs::File, io::BufReader};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+use transactions::{
+    accrual::{AccrualConfig, DayCount},
+    blocklist::Blocklist,
+    follow::Follower,
+    fx::RateTable,
+    input,
+    pipeline::Batches,
+    reorder::ReorderBuffer,
+    rules::RuleSet,
+    spool::Spool,
+    AsOf, Balance, Change, Currency, DisputePolicy, Event, LockPolicy, LockScope,
+    LockedAccountPolicy, State, Transaction, Window,
+};
 
-mod transaction;
-pub use transaction::{ClientId, Transaction, TxId};
+/// How many rows apart checkpoints are taken when reporting as of an earlier point.
+const DEFAULT_CHECKPOINT_EVERY: u64 = 1000;
 
-mod state;
-pub use state::State;
+/// How long `--follow` waits before looking for new rows again, in milliseconds.
+const DEFAULT_POLL_INTERVAL: u64 = 1000;
+
+type Alerts = Option<csv::Writer<File>>;
+type Changes = Option<Box<dyn Write>>;
 
 pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
 
-    let filename = match args.get(1) {
-        Some(name) => name,
-        None => {
-            return Err(
-                "Input file name must be provided as the first argument to this program.".into(),
-            )
+    // flags may appear anywhere; the arguments that aren't are input files, processed in order
+    let (flags, positional): (Vec<&String>, Vec<&String>) =
+        args.iter().skip(1).partition(|arg| arg.starts_with("--"));
+    let print_trial_balance = flags.iter().any(|flag| *flag == "--trial-balance");
+    let extended = flags.iter().any(|flag| *flag == "--extended");
+    let mut state = configure_state(&flags)?;
+    // with `--changes=-` stdout carries the change stream, so the account report is left out
+    let mut changes: Changes = match flag_value(&flags, "--changes") {
+        Some("-") => Some(Box::new(BufWriter::new(io::stdout().lock()))),
+        Some(path) => Some(Box::new(BufWriter::new(File::create(path)?))),
+        None => None,
+    };
+    if changes.is_some() {
+        state = state.with_changes();
+    }
+    let as_of = match (
+        parse_flag(&flags, "--as-of-row", "a number of rows")?.map(AsOf::Row),
+        parse_flag(
+            &flags,
+            "--as-of",
+            "an RFC 3339 timestamp or epoch milliseconds",
+        )?
+        .map(AsOf::Time),
+    ) {
+        (Some(_), Some(_)) => {
+            return Err("Only one of --as-of-row and --as-of may be given.".into())
         }
+        (row, time) => row.or(time),
     };
-    let file = File::open(filename).unwrap();
-    let reader = BufReader::new(file);
-
-    let mut rdr = ReaderBuilder::new().flexible(true).from_reader(reader);
-    let deserialized_stream = rdr.deserialize::<Transaction>();
+    if let Some(as_of) = as_of {
+        let every = parse_flag(&flags, "--checkpoint-every", "a number of rows")?
+            .unwrap_or(DEFAULT_CHECKPOINT_EVERY);
+        state = state.with_checkpoints_for(every, as_of);
+    }
+    let mut reorder =
+        parse_flag(&flags, "--reorder-window", "a number of rows")?.map(ReorderBuffer::new);
 
-    let mut state: State = Default::default();
+    let mut alerts = flag_value(&flags, "--alerts")
+        .map(|path| -> Result<_, Box<dyn std::error::Error>> {
+            let mut wtr = csv::Writer::from_writer(File::create(path)?);
+            wtr.write_record(["row", "time", "client", "tx", "rule", "outcome"])?;
+            Ok(wtr)
+        })
+        .transpose()?;
 
-    for record in deserialized_stream {
-        // You could make this return a result, but I believe `Result` should represent an internal
-        // error in the execution of the program that must be handled. Because transactions are user
-        // data, we don't want to do an excess of work on malformed user data, which is likely to
-        // occur. In fact, it should be considered a _valid_ execution of the function to ignore or
-        // omit invalid transactions.
-        let record: Transaction = match record {
-            Ok(o) => o,
-            // continuing here because invalid transactions should be ignored as stated above
-            Err(_) => continue,
+    let follow_input = flags.iter().any(|flag| *flag == "--follow");
+    let pipeline = flags.iter().any(|flag| *flag == "--pipeline");
+    if pipeline && (follow_input || flag_value(&flags, "--spool").is_some()) {
+        return Err("--pipeline can't be combined with --follow or --spool.".into());
+    }
+    if let Some(dir) = flag_value(&flags, "--spool") {
+        if !positional.is_empty() {
+            return Err(
+                "--spool reads every file in the directory, so takes no input files.".into(),
+            );
+        }
+        if reorder.is_some() || as_of.is_some() {
+            return Err("--spool can't be combined with --reorder-window or --as-of.".into());
+        }
+        let Some(archive) = flag_value(&flags, "--archive") else {
+            return Err("--spool needs --archive=<dir> to move finished files to.".into());
         };
-        state.transact(record);
+        let blocklist = flag_value(&flags, "--blocklist");
+        // the replay is against the blocklist as it is now
+        if let Some(path) = blocklist {
+            state.set_blocklist(Blocklist::from_reader(BufReader::new(File::open(path)?))?);
+        }
+        let mut spool = Spool::open(dir, archive, &mut state)?;
+        if let Some(path) = blocklist {
+            spool = spool.with_blocklist(path);
+        }
+        // whatever the replayed rows raised was written out the first time they were applied
+        state.take_events();
+        state.take_changes();
+        if follow_input {
+            return follow(
+                Source::Spool(spool),
+                &flags,
+                state,
+                extended,
+                alerts,
+                changes,
+            );
+        }
+        spool.process(&mut state, |state| {
+            emit_events(state, &mut alerts, &mut changes)
+        })?;
+    } else if positional.is_empty() {
+        return Err(
+            "Input file name must be provided as the first argument to this program.".into(),
+        );
+    }
+    if follow_input {
+        if reorder.is_some() || as_of.is_some() {
+            return Err("--follow can't be combined with --reorder-window or --as-of.".into());
+        }
+        let [path] = positional.as_slice() else {
+            return Err("--follow takes exactly one input file.".into());
+        };
+        if let Some(path) = flag_value(&flags, "--blocklist") {
+            state.set_blocklist(Blocklist::from_reader(BufReader::new(File::open(path)?))?);
+        }
+        let follower = match flag_value(&flags, "--follow-checkpoint") {
+            Some(checkpoint) => Follower::with_checkpoint(path, checkpoint, &mut state)?,
+            None => Follower::open(path)?,
+        };
+        // whatever the replayed rows raised was written out the first time they were applied
+        state.take_events();
+        state.take_changes();
+        return follow(
+            Source::File(follower),
+            &flags,
+            state,
+            extended,
+            alerts,
+            changes,
+        );
     }
-    println!("{}", state.serialize_to_csv()?);
+    for filename in positional {
+        // the blocklist is read again before every file, so it can be updated in between
+        if let Some(path) = flag_value(&flags, "--blocklist") {
+            state.set_blocklist(Blocklist::from_reader(BufReader::new(File::open(path)?))?);
+        }
+        let reader = input::open(filename)?;
+        let mut apply = |record| -> Result<(), Box<dyn std::error::Error>> {
+            match reorder {
+                Some(ref mut reorder) => reorder.push(&mut state, record),
+                None => state.transact(record),
+            }
+            write_events(&mut state, &mut alerts, &mut changes)
+        };
+
+        if pipeline {
+            // rows are parsed on another thread while the ones before them are applied
+            for batch in Batches::new(reader) {
+                for record in batch? {
+                    apply(record)?;
+                }
+            }
+            continue;
+        }
+        let mut rdr = ReaderBuilder::new().flexible(true).from_reader(reader);
+        // one record is reused for every row, so most rows are read without allocating
+        let mut row = csv::ByteRecord::new();
+
+        // a file that can't be read any further, such as a truncated archive, is not a malformed
+        // row, and skipping it would only fail again
+        while rdr.read_byte_record(&mut row)? {
+            // You could make this return a result, but I believe `Result` should represent an
+            // internal error in the execution of the program that must be handled. Because
+            // transactions are user data, we don't want to do an excess of work on malformed user
+            // data, which is likely to occur. In fact, it should be considered a _valid_ execution
+            // of the function to ignore or omit invalid transactions.
+            let record: Transaction = match Transaction::from_byte_record(&row) {
+                Ok(o) => o,
+                // continuing here because invalid transactions should be ignored as stated above
+                Err(_) => continue,
+            };
+            apply(record)?;
+        }
+    }
+    if let Some(mut alerts) = alerts {
+        alerts.flush()?;
+    }
+    let report_to_stdout = flag_value(&flags, "--changes") != Some("-");
+    if let Some(mut changes) = changes {
+        changes.flush()?;
+    }
+    if let Some(reorder) = reorder {
+        // rows that never matched are reported, but don't stop the account report being written
+        for unmatched in reorder.finish() {
+            eprintln!("Never found the transaction referred to by {:?}", unmatched);
+        }
+    }
+    if let Some(as_of) = as_of {
+        state = state.as_of(as_of);
+    }
+    if print_trial_balance {
+        // stdout is reserved for the account report, so the trial balance goes to stderr
+        let trial_balance = state.trial_balance();
+        eprint!("{}", trial_balance.serialize_to_csv()?);
+        if !trial_balance.is_balanced() {
+            return Err("The ledger does not balance.".into());
+        }
+    }
+    if !report_to_stdout {
+        return Ok(());
+    }
+    println!("{}", report(&state, extended)?);
     Ok(())
 }
+
+/// Where `--follow` reads new rows from.
+enum Source {
+    File(Follower),
+    Spool(Spool),
+}
+
+impl Source {
+    /// Applies whatever is new, calling `emit` to write out what the rows raised as it goes, and
+    /// before any progress is recorded.
+    fn poll(
+        &mut self,
+        state: &mut State,
+        emit: impl FnMut(&mut State) -> Result<(), Box<dyn std::error::Error>>,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        match self {
+            Source::File(follower) => follower.poll(state, emit),
+            Source::Spool(spool) => spool.process(state, emit),
+        }
+    }
+
+    /// The spool keeps its manifest up to date as it goes, so only a file needs checkpointing.
+    fn checkpoint(&mut self) -> io::Result<()> {
+        match self {
+            Source::File(follower) => follower.checkpoint(),
+            Source::Spool(_) => Ok(()),
+        }
+    }
+}
+
+/// Applies new rows from `source` as they arrive, forever. Whenever new rows have been applied,
+/// the account report is written and a checkpoint is taken.
+fn follow(
+    mut source: Source,
+    flags: &[&String],
+    mut state: State,
+    extended: bool,
+    mut alerts: Alerts,
+    mut changes: Changes,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let interval = parse_flag(flags, "--poll-interval", "a number of milliseconds")?
+        .unwrap_or(DEFAULT_POLL_INTERVAL);
+    // with `--changes=-` stdout carries the change stream, so the account report is left out
+    let report_to_stdout = flag_value(flags, "--changes") != Some("-");
+
+    loop {
+        let emit = |state: &mut State| emit_events(state, &mut alerts, &mut changes);
+        if source.poll(&mut state, emit)? > 0 {
+            match flag_value(flags, "--report") {
+                // written alongside and renamed over, so readers never see half a report
+                Some(path) => {
+                    let partial = format!("{}.tmp", path);
+                    fs::write(&partial, report(&state, extended)?)?;
+                    fs::rename(&partial, path)?;
+                }
+                None if report_to_stdout => println!("{}", report(&state, extended)?),
+                None => (),
+            }
+            source.checkpoint()?;
+        }
+        thread::sleep(Duration::from_millis(interval));
+    }
+}
+
+fn report(state: &State, extended: bool) -> Result<String, csv::Error> {
+    if extended {
+        state.serialize_extended_to_csv()
+    } else {
+        state.serialize_to_csv()
+    }
+}
+
+/// Writes out the events and changes since they were last taken, so they don't pile up over a
+/// long run.
+fn write_events(
+    state: &mut State,
+    alerts: &mut Alerts,
+    changes: &mut Changes,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for event in state.take_events() {
+        if let (
+            Some(alerts),
+            Event::RuleBroken {
+                client,
+                tx,
+                rule,
+                outcome,
+                at,
+            },
+        ) = (&mut *alerts, event)
+        {
+            alerts.write_record([
+                at.rows.to_string(),
+                at.time.map(|time| time.to_string()).unwrap_or_default(),
+                client.to_string(),
+                tx.map(|tx| tx.to_string()).unwrap_or_default(),
+                rule.to_string(),
+                outcome.to_string(),
+            ])?;
+        }
+    }
+    if let Some(changes) = changes {
+        for change in state.take_changes() {
+            serde_json::to_writer(&mut *changes, &change_to_json(&change))?;
+            changes.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Like [write_events], and flushes them too, so they are written out before the progress that
+/// made them is recorded.
+fn emit_events(
+    state: &mut State,
+    alerts: &mut Alerts,
+    changes: &mut Changes,
+) -> Result<(), Box<dyn std::error::Error>> {
+    write_events(state, alerts, changes)?;
+    if let Some(alerts) = alerts {
+        alerts.flush()?;
+    }
+    if let Some(changes) = changes {
+        changes.flush()?;
+    }
+    Ok(())
+}
+
+/// Builds the initial state from the command line flags.
+fn configure_state(flags: &[&String]) -> Result<State, Box<dyn std::error::Error>> {
+    let mut state: State = Default::default();
+
+    if let Some(millis) = parse_flag(flags, "--clock-tolerance", "a number of milliseconds")? {
+        state = state.with_clock_tolerance(millis);
+    }
+
+    if let Some(rows) = parse_flag(flags, "--authorization-expiry", "a number of rows")? {
+        state = state.with_authorization_expiry(rows);
+    }
+
+    if let Some(path) = flag_value(flags, "--rules") {
+        let rules = RuleSet::from_reader(BufReader::new(File::open(path)?))?;
+        state = state.with_rules(rules);
+    }
+
+    if let Some(policy) = parse_flag::<LockPolicy>(
+        flags,
+        "--lock-policy",
+        "any, credits, after:<chargebacks>, or ratio:<fraction of deposits>",
+    )? {
+        state = state.with_lock_policy(policy);
+    }
+
+    if let Some(scope) = parse_flag::<LockScope>(flags, "--lock-scope", "client or wallet")? {
+        state = state.with_lock_scope(scope);
+    }
+
+    if let Some(policy) = parse_flag::<LockedAccountPolicy>(
+        flags,
+        "--locked-allow",
+        "a comma separated list of transaction types",
+    )? {
+        state = state.with_locked_account_policy(policy);
+    }
+
+    let dispute_policy = DisputePolicy {
+        filing_window: parse_window(flags, "--dispute-filing")?,
+        expiry: parse_window(flags, "--dispute-expiry")?,
+    };
+    state = state.with_dispute_policy(dispute_policy);
+
+    match (
+        flag_value(flags, "--fx-rates"),
+        parse_flag::<Currency>(flags, "--base-currency", "an ISO 4217 currency code")?,
+    ) {
+        (Some(path), Some(base)) => {
+            let rates = RateTable::from_reader(BufReader::new(File::open(path)?))?;
+            state = state.with_fx(base, rates);
+        }
+        (None, None) => (),
+        _ => return Err("--fx-rates and --base-currency must be given together.".into()),
+    }
+
+    let accrual = AccrualConfig {
+        credit_rate: parse_flag(flags, "--credit-rate", "an annual rate")?.unwrap_or_default(),
+        overdraft_rate: parse_flag(flags, "--overdraft-rate", "an annual rate")?
+            .unwrap_or_default(),
+        period_fee: parse_flag(flags, "--accrual-fee", "an amount")?.unwrap_or_default(),
+        day_count: parse_flag::<DayCount>(flags, "--day-count", "act/365, act/360, or 30/360")?
+            .unwrap_or_default(),
+    };
+    state = state.with_accrual(accrual);
+
+    Ok(state)
+}
+
+/// Parses the value of a flag given as `--name=value`, if it was given.
+fn parse_flag<T: FromStr>(
+    flags: &[&String],
+    name: &str,
+    expected: &str,
+) -> Result<Option<T>, String> {
+    flag_value(flags, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{} must be {}.", name, expected))
+        })
+        .transpose()
+}
+
+/// Parses a [Window] from the `<prefix>-days` and `<prefix>-rows` flags. Days are used for
+/// timestamped input and rows for everything else.
+fn parse_window(flags: &[&String], prefix: &str) -> Result<Option<Window>, String> {
+    let days: Option<u64> = parse_flag(flags, &format!("{}-days", prefix), "a number of days")?;
+    let rows = parse_flag(flags, &format!("{}-rows", prefix), "a number of rows")?;
+    match (days, rows) {
+        (None, None) => return Ok(None),
+        // without rows to fall back on, the window would never close on rows without timestamps
+        (Some(_), None) => {
+            return Err(format!(
+                "{prefix}-days needs {prefix}-rows too, for input without timestamps."
+            ))
+        }
+        _ => (),
+    }
+    Ok(Some(Window {
+        millis: days.and_then(|days| Window::days(days).millis),
+        rows,
+    }))
+}
+
+/// Finds the value of a flag given as `--name=value`.
+fn flag_value<'a>(flags: &[&'a String], name: &str) -> Option<&'a str> {
+    flags.iter().find_map(|flag| {
+        flag.strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
+
+/// One line of the `--changes` stream.
+fn change_to_json(change: &Change) -> serde_json::Value {
+    let balance = |balance: &Balance| {
+        serde_json::json!({
+            "held": balance.held,
+            "total": balance.total,
+            "locked": balance.locked,
+        })
+    };
+    serde_json::json!({
+        "row": change.at.rows,
+        "time": change.at.time.map(|time| time.to_string()),
+        "type": change.cause.kind(),
+        "client": change.client,
+        "account": change.account,
+        "tx": change.cause.tx(),
+        "currency": change.currency.map(|currency| currency.to_string()),
+        "applied": change.result.is_ok(),
+        "error": change.result.as_ref().err().map(|error| error.to_string()),
+        "before": balance(&change.before),
+        "after": balance(&change.after),
+    })
+}
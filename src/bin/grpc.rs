@@ -0,0 +1,16 @@
+use std::env;
+use tokio::net::TcpListener;
+use transactions::{grpc, server, State};
+
+/// Where the server listens unless `--listen=<address>` is given.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:50051";
+
+#[tokio::main]
+pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let address = server::listen_address(&args, DEFAULT_ADDRESS);
+    let listener = TcpListener::bind(address).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    grpc::serve(listener, State::default()).await?;
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use std::{env, net::TcpListener};
+use transactions::{http, server, State};
+
+/// Where the server listens unless `--listen=<address>` is given.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let address = server::listen_address(&args, DEFAULT_ADDRESS);
+    let listener = TcpListener::bind(address)?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    http::serve(listener, State::default())?;
+    Ok(())
+}
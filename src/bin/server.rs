@@ -0,0 +1,15 @@
+use std::{env, net::TcpListener};
+use transactions::{server, State};
+
+/// Where the server listens unless `--listen=<address>` is given.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+pub fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let address = server::listen_address(&args, DEFAULT_ADDRESS);
+    let listener = TcpListener::bind(address)?;
+    // when binding to port 0, this is the only way to find out which port was chosen
+    eprintln!("Listening on {}", listener.local_addr()?);
+    server::serve(listener, State::default())?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use crate::ClientId;
+use fnv::FnvHashMap;
+use std::io::Read;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BlocklistError {
+    #[error("could not read the blocklist: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid blocklist entry on line {line}: {reason}")]
+    InvalidEntry { line: u64, reason: &'static str },
+}
+
+/// Clients who must not move money in or out, read from a local CSV file with the columns
+/// `client, reason`. The reason is kept so that rejections can say why.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist {
+    clients: FnvHashMap<ClientId, String>,
+}
+
+impl Blocklist {
+    pub fn from_reader(reader: impl Read) -> Result<Self, BlocklistError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        let mut blocklist = Blocklist::default();
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            let client = record.get(0).and_then(|client| client.parse().ok()).ok_or(
+                BlocklistError::InvalidEntry {
+                    line,
+                    reason: "expected a client id",
+                },
+            )?;
+            blocklist.insert(client, record.get(1).unwrap_or_default());
+        }
+        Ok(blocklist)
+    }
+
+    pub fn insert(&mut self, client: ClientId, reason: impl Into<String>) {
+        self.clients.insert(client, reason.into());
+    }
+
+    /// Why the client is blocked, if they are.
+    pub fn reason(&self, client: ClientId) -> Option<&str> {
+        self.clients.get(&client).map(String::as_str)
+    }
+}
+
+#[test]
+fn test_blocklist() {
+    let blocklist = Blocklist::from_reader("client,reason\n1,sanctions\n 2 \n".as_bytes()).unwrap();
+    assert_eq!(blocklist.reason(1), Some("sanctions"));
+    assert_eq!(blocklist.reason(2), Some(""));
+    assert_eq!(blocklist.reason(3), None);
+
+    assert!(matches!(
+        Blocklist::from_reader("client,reason\nabc,fraud".as_bytes()),
+        Err(BlocklistError::InvalidEntry { line: 2, .. })
+    ));
+}
@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+/// A calendar date, written `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Date {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl Date {
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+
+    /// The number of days between 1970-01-01 and this date.
+    // This is Howard Hinnant's `days_from_civil`, which treats March as the first month of the
+    // year so that leap days fall at the very end.
+    pub fn days_since_epoch(&self) -> i64 {
+        let month = i64::from(self.month);
+        let year = i64::from(self.year) - i64::from(month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146_097 + day_of_era - 719_468
+    }
+
+    /// The inverse of [Date::days_since_epoch]. This is Howard Hinnant's `civil_from_days`.
+    pub fn from_days_since_epoch(days: i64) -> Self {
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    /// How many days the month has, counting leap years.
+    fn days_in_month(year: u16, month: u8) -> u8 {
+        match month {
+            4 | 6 | 9 | 11 => 30,
+            2 if year.is_multiple_of(4)
+                && (!year.is_multiple_of(100) || year.is_multiple_of(400)) =>
+            {
+                29
+            }
+            2 => 28,
+            _ => 31,
+        }
+    }
+
+    /// The number of days from `earlier` to this date.
+    pub fn days_since(&self, earlier: Date) -> i64 {
+        self.days_since_epoch() - earlier.days_since_epoch()
+    }
+}
+
+impl FromStr for Date {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let mut parts = raw.trim().splitn(3, '-');
+        let mut next = || parts.next().ok_or(());
+        let year = next()?.parse().map_err(|_| ())?;
+        let month = next()?.parse().map_err(|_| ())?;
+        let day = next()?.parse().map_err(|_| ())?;
+        if !(1..=12).contains(&month) || !(1..=Date::days_in_month(year, month)).contains(&day) {
+            return Err(());
+        }
+        Ok(Date { year, month, day })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[test]
+fn test_days_since() {
+    let date = |raw: &str| raw.parse::<Date>().unwrap();
+    assert_eq!(date("1970-01-01").days_since_epoch(), 0);
+    assert_eq!(date("2000-03-01").days_since(date("2000-02-28")), 2);
+    assert_eq!(date("2024-01-01").days_since(date("2023-01-01")), 365);
+    assert_eq!(date("2025-01-01").days_since(date("2024-01-01")), 366);
+    assert_eq!(date("1969-12-31").days_since_epoch(), -1);
+    for raw in ["1970-01-01", "2000-02-29", "2024-12-31", "2100-03-01"] {
+        assert_eq!(
+            Date::from_days_since_epoch(date(raw).days_since_epoch()),
+            date(raw)
+        );
+    }
+}
+
+#[test]
+fn test_invalid_dates() {
+    for raw in [
+        "2024-02-31",
+        "2023-04-31",
+        "2023-02-29",
+        "1900-02-29",
+        "2024-13-01",
+        "2024-01-00",
+    ] {
+        assert!(raw.parse::<Date>().is_err(), "{}", raw);
+    }
+    for raw in ["2024-02-29", "2000-02-29", "2023-04-30", "2023-12-31"] {
+        assert!(raw.parse::<Date>().is_ok(), "{}", raw);
+    }
+}
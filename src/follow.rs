@@ -0,0 +1,344 @@
+use crate::{State, Transaction};
+use std::{
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// How many rows are applied between the calls [Follower::poll] makes to write out what they
+/// raised.
+const EMIT_EVERY: u64 = 1000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FollowError {
+    #[error("could not follow the input: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not read the checkpoint: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid checkpoint: {reason}")]
+    InvalidCheckpoint { reason: &'static str },
+}
+
+/// Reads a CSV file as it grows, like `tail -f`, applying each new row to a [State]. A row is
+/// only read once its line is complete. The first line of every file is a header, and is skipped.
+///
+/// The file may be rotated, either by being replaced or by being truncated. Once the end of the
+/// old file has been read, the new one is read from the start.
+///
+/// With a checkpoint, every row is also appended to a journal, `<checkpoint>.rows`, as it is read.
+/// [Follower::checkpoint] records how far into the file and the journal have been read. Following
+/// again from the same checkpoint replays the journal up to that point to rebuild the state, and
+/// carries on from the same place in the file, so no row is applied twice. If the file was
+/// rotated while nothing was following it, the new file is read from the start.
+#[derive(Debug)]
+pub struct Follower {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// tells the file being read apart from one which replaced it
+    identity: Option<u64>,
+    /// how far into the file complete lines have been read
+    offset: u64,
+    /// whether the next complete line is the file's header
+    at_header: bool,
+    /// the start of a line which hasn't been finished yet
+    partial: Vec<u8>,
+    journal: Option<Journal>,
+}
+
+#[derive(Debug)]
+struct Journal {
+    checkpoint: PathBuf,
+    rows: BufWriter<File>,
+    /// how long the journal is, including rows not yet flushed
+    len: u64,
+}
+
+/// Where a checkpoint says to carry on from.
+struct Saved {
+    offset: u64,
+    journal: u64,
+    identity: Option<u64>,
+}
+
+impl Follower {
+    /// Starts following the file at `path` from its start.
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = File::open(&path)?;
+        Ok(Follower {
+            identity: identity(&file.metadata()?),
+            reader: BufReader::new(file),
+            path,
+            offset: 0,
+            at_header: true,
+            partial: vec![],
+            journal: None,
+        })
+    }
+
+    /// Like [Follower::open], keeping a checkpoint at `checkpoint`. If there already is one, the
+    /// journal is replayed into `state`, and the file is read from where the checkpoint was taken.
+    /// The replay uses whatever blocklist `state` has now, so if it has changed since the rows were
+    /// first applied, the rebuilt accounts can differ from the ones the process had.
+    pub fn with_checkpoint(
+        path: impl Into<PathBuf>,
+        checkpoint: impl Into<PathBuf>,
+        state: &mut State,
+    ) -> Result<Self, FollowError> {
+        let mut follower = Follower::open(path)?;
+        let checkpoint = checkpoint.into();
+        let saved = match File::open(&checkpoint) {
+            Ok(file) => Some(Saved::from_reader(file)?),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut rows = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(journal_path(&checkpoint))?;
+        // rows journalled after the checkpoint was taken will be read from the file again
+        let len = saved.as_ref().map_or(0, |saved| saved.journal);
+        if rows.metadata()?.len() < len {
+            return Err(FollowError::InvalidCheckpoint {
+                reason: "the journal is shorter than the checkpoint says",
+            });
+        }
+        rows.set_len(len)?;
+        for line in BufReader::new(&rows).split(b'\n') {
+            apply(&line?, state);
+        }
+        rows.seek(SeekFrom::End(0))?;
+
+        if let Some(saved) = saved {
+            let same_file = saved.identity == follower.identity;
+            if same_file && follower.reader.get_ref().metadata()?.len() >= saved.offset {
+                follower.reader.seek(SeekFrom::Start(saved.offset))?;
+                follower.offset = saved.offset;
+                follower.at_header = saved.offset == 0;
+            }
+        }
+        follower.journal = Some(Journal {
+            checkpoint,
+            rows: BufWriter::new(rows),
+            len,
+        });
+        Ok(follower)
+    }
+
+    /// Applies every row completed since the last poll, and returns how many there were. When the
+    /// end of the file is reached and it has been rotated, carries on with the new file.
+    ///
+    /// `emit` is called with the state every so many rows, and once more at the end if any rows
+    /// were applied, so that what they raised, such as their events and changes, can be written
+    /// out and taken without holding a whole file's worth. Rows emitted before a crash but after
+    /// the last checkpoint are read again on restart, and emitted again.
+    pub fn poll<E: From<io::Error>>(
+        &mut self,
+        state: &mut State,
+        mut emit: impl FnMut(&mut State) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let mut rows = 0;
+        loop {
+            let read = self.reader.read_until(b'\n', &mut self.partial)?;
+            if !self.partial.ends_with(b"\n") {
+                // the end of the file, or the middle of a line still being written
+                if read == 0 && self.rotated()? {
+                    self.reopen()?;
+                    continue;
+                }
+                if rows % EMIT_EVERY != 0 {
+                    emit(state)?;
+                }
+                return Ok(rows);
+            }
+            let line = std::mem::take(&mut self.partial);
+            self.offset += line.len() as u64;
+            if std::mem::take(&mut self.at_header) {
+                continue;
+            }
+            if let Some(journal) = &mut self.journal {
+                journal.rows.write_all(&line)?;
+                journal.len += line.len() as u64;
+            }
+            apply(&line, state);
+            rows += 1;
+            if rows % EMIT_EVERY == 0 {
+                emit(state)?;
+            }
+        }
+    }
+
+    /// Records how far the file and the journal have been read. Does nothing without a
+    /// checkpoint.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let Some(journal) = &mut self.journal else {
+            return Ok(());
+        };
+        journal.rows.flush()?;
+        journal.rows.get_ref().sync_data()?;
+        // written alongside and renamed over, so a crash leaves either the old or the new one
+        let mut path = journal.checkpoint.clone().into_os_string();
+        path.push(".tmp");
+        let mut file = File::create(&path)?;
+        writeln!(file, "offset,journal,file")?;
+        writeln!(
+            file,
+            "{},{},{}",
+            self.offset,
+            journal.len,
+            self.identity.map(|id| id.to_string()).unwrap_or_default()
+        )?;
+        file.sync_data()?;
+        fs::rename(&path, &journal.checkpoint)
+    }
+
+    /// Whether the file has been replaced or truncated since it was opened. While it is missing,
+    /// it is taken to be about to be replaced, and not rotated yet.
+    fn rotated(&self) -> io::Result<bool> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => Ok(identity(&metadata) != self.identity
+                || metadata.len() < self.offset + self.partial.len() as u64),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let file = File::open(&self.path)?;
+        self.identity = identity(&file.metadata()?);
+        self.reader = BufReader::new(file);
+        self.offset = 0;
+        self.at_header = true;
+        // the old file's last line was never finished, and never will be
+        self.partial.clear();
+        Ok(())
+    }
+}
+
+impl Saved {
+    fn from_reader(reader: impl io::Read) -> Result<Self, FollowError> {
+        let invalid = |reason| FollowError::InvalidCheckpoint { reason };
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let record = rdr
+            .records()
+            .next()
+            .ok_or_else(|| invalid("it is empty"))??;
+        let number = |index| record.get(index).and_then(|value| value.parse().ok());
+        Ok(Saved {
+            offset: number(0).ok_or_else(|| invalid("expected an offset"))?,
+            journal: number(1).ok_or_else(|| invalid("expected a journal length"))?,
+            identity: number(2),
+        })
+    }
+}
+
+/// Rows that can't be read are left out, like they are from any other input.
+fn apply(line: &[u8], state: &mut State) {
+    if let Some(Ok(transaction)) = Transaction::from_line(line) {
+        state.transact(transaction);
+    }
+}
+
+fn journal_path(checkpoint: &Path) -> PathBuf {
+    let mut path = checkpoint.to_path_buf().into_os_string();
+    path.push(".rows");
+    path.into()
+}
+
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+/// Without inode numbers, a replaced file can only be noticed if it is shorter.
+#[cfg(not(unix))]
+fn identity(_: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+fn emit_nothing(_: &mut State) -> io::Result<()> {
+    Ok(())
+}
+
+#[test]
+fn test_follow() {
+    let dir = std::env::temp_dir().join(format!("follow-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let checkpoint = dir.join("checkpoint");
+    let append = |text: &str| {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&input)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    };
+
+    append("type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,");
+    let mut state = State::default();
+    let mut follower = Follower::with_checkpoint(&input, &checkpoint, &mut state).unwrap();
+    assert_eq!(follower.poll(&mut state, emit_nothing).unwrap(), 1);
+    append("5\n");
+    assert_eq!(follower.poll(&mut state, emit_nothing).unwrap(), 1);
+    assert_eq!(follower.poll(&mut state, emit_nothing).unwrap(), 0);
+
+    // rotated, with the last row of the old file written just before
+    append("withdrawal,1,3,1\n");
+    fs::rename(&input, dir.join("input.csv.1")).unwrap();
+    append("type,client,tx,amount\ndispute,1,2\n");
+    assert_eq!(follower.poll(&mut state, emit_nothing).unwrap(), 2);
+    follower.checkpoint().unwrap();
+    // read and journalled, but not checkpointed
+    append("withdrawal,1,4,2\n");
+    assert_eq!(follower.poll(&mut state, emit_nothing).unwrap(), 1);
+    let expected = state.serialize_to_csv().unwrap();
+    drop(follower);
+
+    let mut restarted = State::default();
+    let mut follower = Follower::with_checkpoint(&input, &checkpoint, &mut restarted).unwrap();
+    assert_eq!(follower.poll(&mut restarted, emit_nothing).unwrap(), 1);
+    assert_eq!(restarted.serialize_to_csv().unwrap(), expected);
+    assert_eq!(
+        expected,
+        "client,available,held,total,locked\n1,7,5,12,false\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_follow_emit() {
+    let dir = std::env::temp_dir().join(format!("follow-emit-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let mut rows = "type,client,tx,amount\n".to_string();
+    for tx in 0..EMIT_EVERY * 2 + 5 {
+        rows += &format!("deposit,1,{},1\n", tx);
+    }
+    fs::write(&input, rows).unwrap();
+
+    let mut state = State::default().with_changes();
+    let mut follower = Follower::open(&input).unwrap();
+    let mut emitted = vec![];
+    let mut emit = |state: &mut State| {
+        emitted.push(state.take_changes().len() as u64);
+        io::Result::Ok(())
+    };
+    assert_eq!(
+        follower.poll(&mut state, &mut emit).unwrap(),
+        EMIT_EVERY * 2 + 5
+    );
+    // nothing new, so nothing to emit
+    assert_eq!(follower.poll(&mut state, &mut emit).unwrap(), 0);
+    assert_eq!(emitted, vec![EMIT_EVERY, EMIT_EVERY, 5]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
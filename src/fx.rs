@@ -0,0 +1,143 @@
+use crate::{Currency, Date};
+use std::{collections::HashMap, io::Read};
+
+/// Converted amounts are rounded to this many decimal places, half away from zero. Rounding once,
+/// when the amount is booked, means reversing the transaction later moves exactly the same amount.
+const DECIMAL_PLACES: i32 = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FxError {
+    #[error("could not read the FX rate table: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid FX rate on line {line}: {reason}")]
+    InvalidRate { line: u64, reason: &'static str },
+}
+
+/// Exchange rates read from a local CSV file with the columns `date, pair, rate`. A pair is
+/// written `EUR/USD`, and a rate of `1.1` means one euro buys 1.1 dollars. Rates can be used in
+/// either direction, so there is no need to list both `EUR/USD` and `USD/EUR`.
+#[derive(Debug, Clone, Default)]
+pub struct RateTable {
+    // every rate for a pair, sorted by date
+    rates: HashMap<(Currency, Currency), Vec<(Date, f64)>>,
+}
+
+impl RateTable {
+    pub fn from_reader(reader: impl Read) -> Result<Self, FxError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut table = RateTable::default();
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            let invalid = |reason| FxError::InvalidRate { line, reason };
+            let date = record
+                .get(0)
+                .and_then(|date| date.parse().ok())
+                .ok_or_else(|| invalid("expected a date like 2024-01-31"))?;
+            let (from, to) = record
+                .get(1)
+                .and_then(|pair| pair.split_once('/'))
+                .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+                .ok_or_else(|| invalid("expected a currency pair like EUR/USD"))?;
+            let rate: f64 = record
+                .get(2)
+                .and_then(|rate| rate.parse().ok())
+                .filter(|rate: &f64| rate.is_finite() && *rate > 0.)
+                .ok_or_else(|| invalid("expected a positive rate"))?;
+            table.insert(date, from, to, rate);
+        }
+        Ok(table)
+    }
+
+    pub fn insert(&mut self, date: Date, from: Currency, to: Currency, rate: f64) {
+        let rates = self.rates.entry((from, to)).or_default();
+        let index = rates.partition_point(|(existing, _)| *existing <= date);
+        rates.insert(index, (date, rate));
+    }
+
+    /// The rate for converting `from` into `to` on the date `on`, which is the latest rate dated
+    /// on or before it. Without a date, the newest rate in the table is used.
+    pub fn rate(&self, from: Currency, to: Currency, on: Option<Date>) -> Option<f64> {
+        if from == to {
+            return Some(1.);
+        }
+        let latest = |pair| {
+            let rates: &Vec<(Date, f64)> = self.rates.get(&pair)?;
+            let published = match on {
+                Some(on) => rates.partition_point(|(date, _)| *date <= on),
+                None => rates.len(),
+            };
+            published.checked_sub(1).map(|index| rates[index])
+        };
+        // if both directions are listed, trust whichever was published most recently
+        match (latest((from, to)), latest((to, from))) {
+            (Some((date, rate)), Some((inverse_date, _))) if date >= inverse_date => Some(rate),
+            (_, Some((_, inverse))) => Some(1. / inverse),
+            (Some((_, rate)), None) => Some(rate),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Records how a foreign-currency amount was converted into the base currency when it was booked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Conversion {
+    pub from: Currency,
+    pub original_amount: f64,
+    pub rate: f64,
+}
+
+impl Conversion {
+    /// Converts another amount in the same currency at the same rate. This is used to reverse or
+    /// refund a converted transaction at the rate it was originally booked at.
+    pub fn apply(&self, amount: f64) -> f64 {
+        convert(amount, self.rate)
+    }
+}
+
+/// Converts `amount` at `rate`, rounding the result deterministically.
+pub fn convert(amount: f64, rate: f64) -> f64 {
+    round(amount * rate)
+}
+
+/// Rounds a computed amount to a fixed number of decimal places, half away from zero. Anything
+/// that derives an amount, rather than reading it from the input, rounds it with this.
+pub fn round(amount: f64) -> f64 {
+    let scale = 10f64.powi(DECIMAL_PLACES);
+    // `f64::round` rounds half away from zero
+    (amount * scale).round() / scale
+}
+
+#[test]
+fn test_rate_table() {
+    let rates = r#"date,pair,rate
+2024-01-02, EUR/USD, 1.10
+2024-01-01, EUR/USD, 1.05
+2024-01-01, USD/JPY, 140
+"#;
+    let table = RateTable::from_reader(rates.as_bytes()).unwrap();
+    let eur = "EUR".parse().unwrap();
+    let usd = "USD".parse().unwrap();
+    let jpy = "JPY".parse().unwrap();
+
+    assert_eq!(table.rate(eur, usd, None), Some(1.10));
+    assert_eq!(table.rate(usd, eur, None), Some(1. / 1.10));
+    assert_eq!(table.rate(jpy, usd, None), Some(1. / 140.));
+    assert_eq!(table.rate(eur, jpy, None), None);
+    assert_eq!(convert(10., table.rate(usd, eur, None).unwrap()), 9.0909);
+    assert_eq!(convert(1., table.rate(jpy, usd, None).unwrap()), 0.0071);
+
+    // a rate only applies from its own date on
+    let date = |raw: &str| raw.parse().ok();
+    assert_eq!(table.rate(eur, usd, date("2023-12-31")), None);
+    assert_eq!(table.rate(eur, usd, date("2024-01-01")), Some(1.05));
+    assert_eq!(table.rate(usd, eur, date("2024-01-01")), Some(1. / 1.05));
+    assert_eq!(table.rate(eur, usd, date("2024-06-30")), Some(1.10));
+
+    assert!(matches!(
+        RateTable::from_reader("date,pair,rate\n2024-01-01,EURUSD,1.1".as_bytes()),
+        Err(FxError::InvalidRate { line: 2, .. })
+    ));
+}
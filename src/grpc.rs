@@ -0,0 +1,210 @@
+// every handler returns tonic's `Status`, which is large, but it is not ours to shrink
+#![allow(clippy::result_large_err)]
+
+use crate::{server, AccountReport, ClientId, State, Transaction};
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, mpsc},
+};
+use tokio_stream::{
+    wrappers::{ReceiverStream, TcpListenerStream},
+    Stream, StreamExt,
+};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+/// The messages and service generated from `proto/transactions.proto`.
+pub mod proto {
+    tonic::include_proto!("transactions");
+}
+use proto::transaction_processor_server::{TransactionProcessor, TransactionProcessorServer};
+
+/// How many reports a watcher may have waiting to be sent before the watch waits for them.
+const WATCH_BUFFER: usize = 16;
+
+/// How many batches of changed clients a watcher may fall behind by before it has to check its
+/// client's report without knowing whether it changed.
+const CHANGED_BUFFER: usize = 64;
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Serves the gRPC service on `listener` until the server fails. Every request is applied to
+/// `state`, one at a time.
+pub async fn serve(listener: TcpListener, state: State) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(TransactionProcessorServer::new(Processor::new(state)))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+}
+
+/// The gRPC service, applying transactions from every request to one shared [State].
+#[derive(Clone)]
+pub struct Processor {
+    state: Arc<Mutex<State>>,
+    /// Sent the clients each transaction changed, so watchers only report again for their own.
+    /// These come from the state's changes rather than the row, because expiring disputes and
+    /// accruals change clients other than the one the row was for.
+    changed: broadcast::Sender<Arc<[ClientId]>>,
+}
+
+impl Processor {
+    /// Serves `state`, which records its changes from now on to tell watchers what changed.
+    pub fn new(state: State) -> Self {
+        let (changed, _) = broadcast::channel(CHANGED_BUFFER);
+        Processor {
+            state: Arc::new(Mutex::new(state.with_changes())),
+            changed,
+        }
+    }
+
+    fn submit(&self, transaction: proto::Transaction) -> proto::SubmitResult {
+        let mut clients = vec![];
+        let result = to_transaction(transaction)
+            .ok_or_else(|| "could not read the transaction".to_string())
+            .and_then(|transaction| {
+                let mut state = server::lock(&self.state);
+                let result = state.try_transact(transaction);
+                // a rejected row still gives a new client a row in the report
+                clients = state
+                    .take_changes()
+                    .into_iter()
+                    .map(|change| change.client)
+                    .collect();
+                result.map_err(|error| error.to_string())
+            });
+        if !clients.is_empty() {
+            // there may be nobody watching
+            let _ = self.changed.send(clients.into());
+        }
+        proto::SubmitResult {
+            applied: result.is_ok(),
+            error: result.err(),
+        }
+    }
+
+    fn report(&self, client: ClientId) -> proto::AccountReport {
+        proto::AccountReport {
+            accounts: server::lock(&self.state)
+                .accounts_of(client)
+                .iter()
+                .map(to_account)
+                .collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl TransactionProcessor for Processor {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitResult>, Status> {
+        Ok(Response::new(self.submit(request.into_inner())))
+    }
+
+    type SubmitTransactionsStream = ResponseStream<proto::SubmitResult>;
+
+    async fn submit_transactions(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::SubmitTransactionsStream>, Status> {
+        let processor = self.clone();
+        let results = request
+            .into_inner()
+            .map(move |transaction| Ok(processor.submit(transaction?)));
+        Ok(Response::new(Box::pin(results)))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::AccountReport>, Status> {
+        let client = to_client(request.into_inner().client)?;
+        let report = self.report(client);
+        if report.accounts.is_empty() {
+            return Err(Status::not_found("unknown client"));
+        }
+        Ok(Response::new(report))
+    }
+
+    type WatchAccountStream = ResponseStream<proto::AccountReport>;
+
+    /// Sends the client's report straight away, even if the client is not known yet, and again
+    /// whenever a transaction changes it.
+    async fn watch_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<Self::WatchAccountStream>, Status> {
+        let client = to_client(request.into_inner().client)?;
+        let processor = self.clone();
+        // subscribe before the first report, so no change can be missed in between
+        let mut changed = self.changed.subscribe();
+        let (reports, received) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            let mut last = None;
+            loop {
+                let report = processor.report(client);
+                if last.as_ref() != Some(&report) {
+                    if reports.send(Ok(report.clone())).await.is_err() {
+                        return;
+                    }
+                    last = Some(report);
+                }
+                // waits for a transaction which changed the client
+                loop {
+                    tokio::select! {
+                        change = changed.recv() => match change {
+                            Ok(clients) if clients.contains(&client) => break,
+                            Ok(_) => (),
+                            // a watcher that fell behind may have missed its client, and only
+                            // needs the latest report
+                            Err(broadcast::error::RecvError::Lagged(_)) => break,
+                            Err(broadcast::error::RecvError::Closed) => return,
+                        },
+                        _ = reports.closed() => return,
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(received))))
+    }
+}
+
+fn to_client(client: u32) -> Result<ClientId, Status> {
+    ClientId::try_from(client).map_err(|_| Status::invalid_argument("client id out of range"))
+}
+
+/// Lays the message's fields out as CSV columns, so it is read exactly as a row of a file would
+/// be. Accruals give their date in place of the client.
+fn to_transaction(transaction: proto::Transaction) -> Option<Transaction> {
+    let optional = |column: Option<String>| column.unwrap_or_default();
+    Transaction::from_columns([
+        transaction.r#type,
+        transaction
+            .date
+            .unwrap_or_else(|| transaction.client.to_string()),
+        transaction.tx.to_string(),
+        optional(transaction.amount.map(|amount| amount.to_string())),
+        optional(transaction.currency),
+        optional(transaction.original_tx.map(|tx| tx.to_string())),
+        optional(transaction.timestamp.map(|millis| millis.to_string())),
+        optional(transaction.account.map(|account| account.to_string())),
+    ])
+    .ok()
+}
+
+fn to_account(account: &AccountReport) -> proto::Account {
+    proto::Account {
+        client: account.client.into(),
+        account: account.account.map(Into::into),
+        currency: account.currency.map(|currency| currency.to_string()),
+        available: account.available,
+        held: account.held,
+        total: account.total,
+        locked: account.locked,
+        lock_reason: account.lock_reason.map(|reason| reason.to_string()),
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{server::lock, AccountReport, State, Transaction, TransactionStatus, TxId};
+use serde_json::{json, Value};
+use std::{
+    io,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+    thread,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// The JSON fields a transaction is read from, in the order of the CSV columns. Accruals give
+/// their date in `date` rather than `client`.
+const FIELDS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "currency",
+    "original_tx",
+    "timestamp",
+    "account",
+];
+
+/// Serves the HTTP API on `listener` until the listener fails. Every request gets its own
+/// thread, and they share `state`.
+///
+/// - `POST /transactions` applies a transaction, or an array of them in order, given as JSON
+///   objects with the same fields as the CSV columns. Each is answered with whether it was
+///   applied, and why not if it wasn't.
+/// - `GET /accounts` lists the same rows as the CSV report.
+/// - `GET /accounts/{client}` lists the rows for one client.
+/// - `GET /transactions/{tx}` says where a deposit, withdrawal, authorization, or refund stands.
+pub fn serve(listener: TcpListener, state: State) -> io::Result<()> {
+    let server = Server::from_listener(listener, None).map_err(io::Error::other)?;
+    let state = Arc::new(Mutex::new(state));
+    for request in server.incoming_requests() {
+        let state = Arc::clone(&state);
+        thread::spawn(move || {
+            let _ = handle(request, &state);
+        });
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, state: &Mutex<State>) -> io::Result<()> {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    let (status, body) = match (request.method(), segments.as_slice()) {
+        (Method::Post, ["transactions"]) => {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => submit(&body, state),
+                Err(_) => error(400, "could not read the body"),
+            }
+        }
+        (Method::Get, ["accounts"]) => {
+            let accounts = lock(state).accounts();
+            (200, Value::from_iter(accounts.iter().map(account_to_json)))
+        }
+        (Method::Get, ["accounts", client]) => match client.parse() {
+            Ok(client) => {
+                let accounts = lock(state).accounts_of(client);
+                if accounts.is_empty() {
+                    error(404, "unknown client")
+                } else {
+                    (200, Value::from_iter(accounts.iter().map(account_to_json)))
+                }
+            }
+            Err(_) => error(400, "expected a client id"),
+        },
+        (Method::Get, ["transactions", tx]) => match tx.parse() {
+            Ok(tx) => match lock(state).transaction_status(tx) {
+                Some(status) => (200, status_to_json(tx, &status)),
+                None => error(404, "unknown transaction"),
+            },
+            Err(_) => error(400, "expected a transaction id"),
+        },
+        (_, ["transactions"] | ["accounts"] | ["accounts", _] | ["transactions", _]) => {
+            error(405, "method not allowed")
+        }
+        _ => error(404, "not found"),
+    };
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    request.respond(
+        Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(content_type),
+    )
+}
+
+fn error(status: u16, message: &str) -> (u16, Value) {
+    (status, json!({ "error": message }))
+}
+
+/// Applies a single transaction or an array of them. A batch is applied without any other
+/// request's transactions in between.
+fn submit(body: &str, state: &Mutex<State>) -> (u16, Value) {
+    let Ok(body) = serde_json::from_str::<Value>(body) else {
+        return error(400, "the body is not JSON");
+    };
+    let mut state = lock(state);
+    let mut apply = |value: &Value| {
+        let result = parse(value)
+            .ok_or_else(|| "could not read the transaction".to_string())
+            .and_then(|transaction| {
+                state
+                    .try_transact(transaction)
+                    .map_err(|error| error.to_string())
+            });
+        match result {
+            Ok(()) => json!({ "applied": true }),
+            Err(error) => json!({ "applied": false, "error": error }),
+        }
+    };
+    match body {
+        Value::Array(values) => (200, Value::from_iter(values.iter().map(apply))),
+        value => (200, apply(&value)),
+    }
+}
+
+/// Reads a transaction from a JSON object by laying its fields out as CSV columns.
+fn parse(value: &Value) -> Option<Transaction> {
+    let object = value.as_object()?;
+    let columns = FIELDS
+        .iter()
+        .map(|field| {
+            let value = match *field {
+                "client" => object.get("client").or_else(|| object.get("date")),
+                field => object.get(field),
+            };
+            match value {
+                None | Some(Value::Null) => Some(String::new()),
+                Some(Value::String(value)) => Some(value.clone()),
+                Some(Value::Number(value)) => Some(value.to_string()),
+                Some(_) => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Transaction::from_columns(columns).ok()
+}
+
+fn account_to_json(account: &AccountReport) -> Value {
+    json!({
+        "client": account.client,
+        // `null` is the row adding up all of the client's wallets
+        "account": account.account,
+        "currency": account.currency.map(|currency| currency.to_string()),
+        "available": account.available,
+        "held": account.held,
+        "total": account.total,
+        "locked": account.locked,
+        "lock_reason": account.lock_reason.map(|reason| reason.to_string()),
+    })
+}
+
+fn status_to_json(tx: TxId, status: &TransactionStatus) -> Value {
+    json!({
+        "tx": tx,
+        "client": status.client,
+        "account": status.account,
+        "amount": status.amount,
+        "currency": status.currency.map(|currency| currency.to_string()),
+        "disputable": status.disputable,
+        "dispute": status.dispute.to_string(),
+    })
+}
@@ -0,0 +1,84 @@
+use flate2::bufread::MultiGzDecoder;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// How an input file is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Tells from the first bytes of a file, or failing that its extension, how it is compressed.
+    /// The extension only matters for files too short or too broken to start with the magic
+    /// bytes, which are then reported as broken rather than read as CSV.
+    pub fn detect(start: &[u8], path: &Path) -> Self {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if start.starts_with(&GZIP_MAGIC) || matches!(extension, Some("gz" | "gzip")) {
+            Compression::Gzip
+        } else if start.starts_with(&ZSTD_MAGIC) || matches!(extension, Some("zst" | "zstd")) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Opens the file at `path` for reading, decompressing it as it is read if it is gzip or zstd.
+/// Only a buffer's worth of the file is held in memory at a time, however large it is.
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn Read + Send>> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    let compression = Compression::detect(reader.fill_buf()?, path);
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        // concatenated gzip members are read one after another, as `gzip -d` does
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+#[test]
+fn test_open() {
+    use std::io::Write;
+
+    let dir = std::env::temp_dir().join(format!("input-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rows = "type,client,tx,amount\ndeposit,1,1,10\n";
+
+    std::fs::write(dir.join("plain.csv"), rows).unwrap();
+    let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gzip.write_all(rows.as_bytes()).unwrap();
+    // named as if it weren't compressed, so only the magic bytes give it away
+    std::fs::write(dir.join("gzip.csv"), gzip.finish().unwrap()).unwrap();
+    std::fs::write(
+        dir.join("zstd.csv.zst"),
+        zstd::encode_all(rows.as_bytes(), 0).unwrap(),
+    )
+    .unwrap();
+    std::fs::write(dir.join("broken.csv.gz"), rows).unwrap();
+
+    for name in ["plain.csv", "gzip.csv", "zstd.csv.zst"] {
+        let mut read = String::new();
+        open(dir.join(name))
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, rows, "{}", name);
+    }
+    let mut read = String::new();
+    open(dir.join("broken.csv.gz"))
+        .unwrap()
+        .read_to_string(&mut read)
+        .unwrap_err();
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
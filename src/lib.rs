@@ -1,5 +1,30 @@
 mod transaction;
-pub use transaction::{ClientId, Transaction, TxId};
+pub use transaction::{AccountId, ClientId, Currency, Transaction, TxId, MAIN_ACCOUNT};
+
+mod date;
+pub use date::Date;
+
+mod timestamp;
+pub use timestamp::Timestamp;
+
+pub mod accrual;
+pub mod blocklist;
+pub mod follow;
+pub mod fx;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod input;
+pub mod pipeline;
+pub mod reorder;
+pub mod rules;
+pub mod server;
+pub mod spool;
 
 mod state;
-pub use state::State;
+pub use state::{
+    AccountReport, AsOf, Balance, Cause, Change, Clock, DisputePolicy, DisputeStatus, Event,
+    LedgerAccount, LockPolicy, LockReason, LockScope, LockedAccountPolicy, State, TransactionError,
+    TransactionStatus, TrialBalance, Window,
+};
@@ -0,0 +1,101 @@
+use crate::Transaction;
+use std::{
+    io::Read,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// How many transactions are parsed before they are handed over together.
+const BATCH_SIZE: usize = 1024;
+
+/// How many parsed batches may wait to be applied before parsing waits for them.
+const QUEUED_BATCHES: usize = 8;
+
+/// Transactions read from a CSV input on another thread, so that parsing the next rows overlaps
+/// with applying the last ones. They come in the same order as reading the input directly would
+/// give them, in batches to keep the cost of handing them over low.
+///
+/// Rows that can't be read are left out, like they are from any other input. If the input itself
+/// can't be read any further, the error comes after every batch read before it, and is the last
+/// item. Dropping the batches stops the parsing thread.
+pub struct Batches {
+    received: Receiver<Result<Vec<Transaction>, csv::Error>>,
+}
+
+impl Batches {
+    /// Starts parsing `reader`, a CSV input with a header row.
+    pub fn new(reader: impl Read + Send + 'static) -> Self {
+        // bounded, so a parser that is well ahead doesn't hold the whole input in memory
+        let (batches, received) = mpsc::sync_channel(QUEUED_BATCHES);
+        thread::spawn(move || {
+            let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+            let mut row = csv::ByteRecord::new();
+            let mut batch = Vec::with_capacity(BATCH_SIZE);
+            loop {
+                match rdr.read_byte_record(&mut row) {
+                    Ok(true) => (),
+                    Ok(false) => break,
+                    Err(error) => {
+                        if !batch.is_empty() {
+                            let _ = batches.send(Ok(batch));
+                        }
+                        let _ = batches.send(Err(error));
+                        return;
+                    }
+                }
+                if let Ok(transaction) = Transaction::from_byte_record(&row) {
+                    batch.push(transaction);
+                }
+                if batch.len() == BATCH_SIZE {
+                    let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH_SIZE));
+                    if batches.send(Ok(full)).is_err() {
+                        // nobody wants the rest
+                        return;
+                    }
+                }
+            }
+            if !batch.is_empty() {
+                let _ = batches.send(Ok(batch));
+            }
+        });
+        Batches { received }
+    }
+}
+
+impl Iterator for Batches {
+    type Item = Result<Vec<Transaction>, csv::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.received.recv().ok()
+    }
+}
+
+#[test]
+fn test_batches() {
+    use crate::State;
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/data/bulk_2.csv");
+    let mut input = std::fs::read(path).unwrap();
+    input.extend_from_slice(b"deposit,1\nnot a row\ndeposit,1,999999,1\n");
+
+    let mut sequential = State::default();
+    let mut rdr = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(input.as_slice());
+    for transaction in rdr.deserialize().flatten() {
+        sequential.transact(transaction);
+    }
+
+    let mut pipelined = State::default();
+    let batches = Batches::new(std::io::Cursor::new(input)).collect::<Vec<_>>();
+    assert!(batches.len() > 1);
+    for batch in batches {
+        for transaction in batch.unwrap() {
+            pipelined.transact(transaction);
+        }
+    }
+    assert_eq!(
+        pipelined.serialize_to_csv().unwrap(),
+        sequential.serialize_to_csv().unwrap()
+    );
+}
@@ -0,0 +1,96 @@
+use crate::{State, Transaction, TxId};
+use fnv::FnvHashMap;
+use std::collections::VecDeque;
+
+/// Sits in front of [State::transact] and holds back disputes, resolutions, and chargebacks that
+/// refer to a transaction the state hasn't seen yet. This happens when feeds from several sources
+/// are merged, and a dispute arrives before the deposit it disputes. Held back rows are applied, in
+/// the order they arrived, straight after the transaction they refer to. Rows that are still
+/// waiting after `window` further rows are given up on and reported as unmatched.
+///
+/// Held back rows only advance the state's [crate::Clock] once they are applied.
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    window: u64,
+    /// how many rows have been pushed so far
+    rows: u64,
+    /// rows waiting for their transaction, in the order they arrived
+    parked: FnvHashMap<TxId, VecDeque<Transaction>>,
+    // Every parked row waits for the same number of rows, so they run out in the order they were
+    // parked. Entries are the row at which a parked row is given up on, and the transaction it is
+    // waiting for. Entries whose rows have since been applied are skipped.
+    deadlines: VecDeque<(u64, TxId)>,
+    unmatched: Vec<Transaction>,
+}
+
+impl ReorderBuffer {
+    /// Creates a buffer which holds rows back for at most `window` further rows.
+    pub fn new(window: u64) -> Self {
+        ReorderBuffer {
+            window,
+            rows: 0,
+            parked: Default::default(),
+            deadlines: Default::default(),
+            unmatched: vec![],
+        }
+    }
+
+    /// Applies `transaction` to `state`, unless it refers to a transaction that hasn't been seen
+    /// yet, in which case it is held back.
+    pub fn push(&mut self, state: &mut State, transaction: Transaction) {
+        self.rows += 1;
+        self.give_up_on_expired();
+
+        let tx = transaction.tx();
+        match (&transaction, tx) {
+            (
+                Transaction::Dispute { .. }
+                | Transaction::Resolve { .. }
+                | Transaction::Chargeback { .. },
+                Some(tx),
+            ) if !state.has_transaction(tx) => {
+                self.parked.entry(tx).or_default().push_back(transaction);
+                self.deadlines.push_back((self.rows + self.window, tx));
+                return;
+            }
+            _ => state.transact(transaction),
+        }
+
+        if let Some(tx) = tx.filter(|tx| state.has_transaction(*tx)) {
+            for parked in self.parked.remove(&tx).into_iter().flatten() {
+                state.transact(parked);
+            }
+        }
+    }
+
+    /// Removes and returns the rows that were given up on since the last call.
+    pub fn take_unmatched(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.unmatched)
+    }
+
+    /// Gives up on every row still being held back, and returns them along with any other
+    /// unmatched rows that haven't been taken yet. This should be called at the end of the input.
+    pub fn finish(mut self) -> Vec<Transaction> {
+        while let Some((_, tx)) = self.deadlines.pop_front() {
+            if let Some(parked) = self.parked.get_mut(&tx).and_then(VecDeque::pop_front) {
+                self.unmatched.push(parked);
+            }
+        }
+        self.unmatched
+    }
+
+    fn give_up_on_expired(&mut self) {
+        while let Some((deadline, tx)) = self.deadlines.front().copied() {
+            if deadline >= self.rows {
+                break;
+            }
+            self.deadlines.pop_front();
+            if let Some(parked) = self.parked.get_mut(&tx) {
+                self.unmatched.extend(parked.pop_front());
+                if parked.is_empty() {
+                    self.parked.remove(&tx);
+                }
+            }
+        }
+    }
+}
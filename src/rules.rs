@@ -0,0 +1,289 @@
+use crate::{ClientId, Clock, Transaction, Window};
+use fnv::FnvHashMap;
+use std::{collections::VecDeque, fmt, io::Read, str::FromStr};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuleError {
+    #[error("could not read the rules: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid rule on line {line}: {reason}")]
+    InvalidRule { line: u64, reason: &'static str },
+}
+
+/// What happens to a transaction that breaks a rule. If a transaction breaks several rules, the
+/// most severe outcome wins, in the order listed here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    /// The transaction goes ahead as normal.
+    Allow,
+    /// The transaction goes ahead, and an alert is raised.
+    Flag,
+    /// The transaction is rejected, and an alert is raised.
+    Reject,
+    /// The transaction is rejected, the client's account is locked, and an alert is raised.
+    Lock,
+}
+
+impl FromStr for Outcome {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "allow" => Ok(Outcome::Allow),
+            "flag" => Ok(Outcome::Flag),
+            "reject" => Ok(Outcome::Reject),
+            "lock" => Ok(Outcome::Lock),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Outcome::Allow => "allow",
+            Outcome::Flag => "flag",
+            Outcome::Reject => "reject",
+            Outcome::Lock => "lock",
+        })
+    }
+}
+
+/// A pattern of client activity worth acting on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    /// A withdrawal when the client has already made `count` withdrawals within `window`.
+    MaxWithdrawals { count: u64, window: Window },
+    /// A single deposit of more than `amount`, as given in the input, before any conversion.
+    MaxDeposit { amount: f64 },
+    /// A dispute which would take the client's disputes over `percent` of their deposits.
+    MaxDisputePercent { percent: f64 },
+}
+
+impl Rule {
+    /// The name the rule is given in the rules file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::MaxWithdrawals { .. } => "max_withdrawals",
+            Rule::MaxDeposit { .. } => "max_deposit",
+            Rule::MaxDisputePercent { .. } => "max_dispute_percent",
+        }
+    }
+}
+
+/// What a client has done so far, as far as the rules are concerned.
+#[derive(Debug, Clone, Default)]
+struct ClientHistory {
+    /// when each recent withdrawal was made, oldest first
+    withdrawals: VecDeque<Clock>,
+    deposits: u64,
+    disputes: u64,
+}
+
+/// Rules read from a local CSV file with the columns `rule, limit, window, outcome`, for example:
+///
+/// ```text
+/// rule,limit,window,outcome
+/// max_withdrawals,3,7d,reject
+/// max_deposit,10000,,flag
+/// max_dispute_percent,50,,lock
+/// ```
+///
+/// A window is either a number of days, like `7d`, or a number of rows, like `100`. Only
+/// `max_withdrawals` has one.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<(Rule, Outcome)>,
+    history: FnvHashMap<ClientId, ClientHistory>,
+}
+
+impl RuleSet {
+    pub fn from_reader(reader: impl Read) -> Result<Self, RuleError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader);
+        let mut rules = RuleSet::default();
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.position().map(|p| p.line()).unwrap_or_default();
+            let invalid = |reason| RuleError::InvalidRule { line, reason };
+            let limit = record
+                .get(1)
+                .and_then(|limit| limit.parse().ok())
+                .filter(|limit: &f64| limit.is_finite() && *limit >= 0.)
+                .ok_or_else(|| invalid("expected a limit of zero or more"))?;
+            let rule = match record.get(0) {
+                Some("max_withdrawals") => Rule::MaxWithdrawals {
+                    count: limit as u64,
+                    window: record
+                        .get(2)
+                        .and_then(parse_window)
+                        .ok_or_else(|| invalid("expected a window like 7d or 100"))?,
+                },
+                Some("max_deposit") => Rule::MaxDeposit { amount: limit },
+                Some("max_dispute_percent") => Rule::MaxDisputePercent { percent: limit },
+                _ => return Err(invalid("unknown rule")),
+            };
+            let outcome = record
+                .get(3)
+                .and_then(|outcome| outcome.parse().ok())
+                .ok_or_else(|| invalid("expected allow, flag, reject, or lock"))?;
+            rules.push(rule, outcome);
+        }
+        Ok(rules)
+    }
+
+    pub fn push(&mut self, rule: Rule, outcome: Outcome) {
+        self.rules.push((rule, outcome));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Every rule that `transaction` would break if it were applied at `clock`.
+    pub fn check(&self, transaction: &Transaction, clock: Clock) -> Vec<(Rule, Outcome)> {
+        let Some(client) = transaction.client() else {
+            return vec![];
+        };
+        let history = self.history.get(&client);
+        self.rules
+            .iter()
+            .copied()
+            .filter(|(rule, _)| match (rule, transaction) {
+                (Rule::MaxWithdrawals { count, window }, Transaction::Withdrawal { .. }) => {
+                    let recent = history.map_or(0, |history| {
+                        history
+                            .withdrawals
+                            .iter()
+                            .filter(|at| !clock.has_elapsed(**at, *window))
+                            .count()
+                    });
+                    recent as u64 >= *count
+                }
+                (Rule::MaxDeposit { amount: limit }, Transaction::Deposit { amount, .. }) => {
+                    amount > limit
+                }
+                (Rule::MaxDisputePercent { percent }, Transaction::Dispute { .. }) => {
+                    let (deposits, disputes) =
+                        history.map_or((0, 0), |history| (history.deposits, history.disputes));
+                    (disputes + 1) as f64 * 100. > percent * deposits as f64
+                }
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Remembers a transaction that was applied at `clock`, so later transactions can be checked
+    /// against it.
+    pub fn record(&mut self, transaction: &Transaction, clock: Clock) {
+        let Some(client) = transaction.client() else {
+            return;
+        };
+        // withdrawals older than every window can never count towards a rule again
+        let windows = self
+            .rules
+            .iter()
+            .filter_map(|(rule, _)| match rule {
+                Rule::MaxWithdrawals { window, .. } => Some(*window),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let history = self.history.entry(client).or_default();
+        match transaction {
+            Transaction::Deposit { .. } => history.deposits += 1,
+            Transaction::Dispute { .. } => history.disputes += 1,
+            Transaction::Withdrawal { .. } if !windows.is_empty() => {
+                history.withdrawals.push_back(clock);
+                while history
+                    .withdrawals
+                    .front()
+                    .is_some_and(|at| windows.iter().all(|window| clock.has_elapsed(*at, *window)))
+                {
+                    history.withdrawals.pop_front();
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Parses a window given as a number of days, like `7d`, or a number of rows, like `100`.
+fn parse_window(raw: &str) -> Option<Window> {
+    match raw.strip_suffix('d') {
+        Some(days) => days.parse().ok().map(Window::days),
+        None => raw.parse().ok().map(Window::rows),
+    }
+}
+
+#[test]
+fn test_rule_set() {
+    let rules = r#"rule,limit,window,outcome
+max_withdrawals,2,3,reject
+max_deposit,100,,flag
+max_dispute_percent,50,,lock
+"#;
+    let mut rules = RuleSet::from_reader(rules.as_bytes()).unwrap();
+    let at = |rows| Clock { rows, time: None };
+    let withdrawal = Transaction::Withdrawal {
+        client: 1,
+        account: 0,
+        tx: 1,
+        amount: 1.,
+        currency: None,
+        timestamp: None,
+    };
+    let deposit = |amount| Transaction::Deposit {
+        client: 1,
+        account: 0,
+        tx: 2,
+        amount,
+        currency: None,
+        timestamp: None,
+    };
+    let dispute = Transaction::Dispute {
+        client: 1,
+        account: 0,
+        tx: 2,
+        timestamp: None,
+    };
+    let broken = |rules: &RuleSet, transaction: &Transaction, rows| {
+        rules
+            .check(transaction, at(rows))
+            .into_iter()
+            .map(|(rule, outcome)| (rule.name(), outcome))
+            .collect::<Vec<_>>()
+    };
+
+    rules.record(&withdrawal, at(1));
+    rules.record(&withdrawal, at(2));
+    assert_eq!(
+        broken(&rules, &withdrawal, 3),
+        vec![("max_withdrawals", Outcome::Reject)]
+    );
+    assert_eq!(broken(&rules, &withdrawal, 5), vec![]);
+
+    assert_eq!(broken(&rules, &deposit(100.), 5), vec![]);
+    assert_eq!(
+        broken(&rules, &deposit(100.01), 5),
+        vec![("max_deposit", Outcome::Flag)]
+    );
+
+    assert_eq!(
+        broken(&rules, &dispute, 5),
+        vec![("max_dispute_percent", Outcome::Lock)]
+    );
+    rules.record(&deposit(1.), at(5));
+    rules.record(&deposit(1.), at(6));
+    assert_eq!(broken(&rules, &dispute, 7), vec![]);
+    rules.record(&dispute, at(7));
+    assert_eq!(
+        broken(&rules, &dispute, 8),
+        vec![("max_dispute_percent", Outcome::Lock)]
+    );
+
+    assert!(matches!(
+        RuleSet::from_reader("rule,limit,window,outcome\nmax_withdrawals,2,,flag".as_bytes()),
+        Err(RuleError::InvalidRule { line: 2, .. })
+    ));
+}
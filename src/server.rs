@@ -0,0 +1,146 @@
+use crate::{State, Transaction};
+#[cfg(any(feature = "http", feature = "grpc"))]
+use std::sync::{Mutex, MutexGuard};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// The address given with `--listen=<address>`, or `default` if there isn't one. `args` are the
+/// arguments without the program name.
+pub fn listen_address<'a>(args: &'a [String], default: &'a str) -> &'a str {
+    args.iter()
+        .find_map(|arg| arg.strip_prefix("--listen="))
+        .unwrap_or(default)
+}
+
+/// Locks the state, carrying on even if another request panicked while holding it.
+#[cfg(any(feature = "http", feature = "grpc"))]
+pub(crate) fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A line from a client, and where to send the reply.
+struct Request {
+    line: String,
+    reply: Sender<String>,
+}
+
+/// Serves the line protocol on `listener` for as long as the listener lasts. A connection which
+/// can't be accepted, for example because the process is out of file descriptors, is logged to
+/// stderr and the next one is waited for. Every connection gets its own thread, but every line is
+/// handed to a single thread which owns `state`, so lines are applied one at a time in the order
+/// they arrive.
+///
+/// Each line is one of:
+///
+/// - a CSV row without a header, like `deposit,1,1,10`, which is answered with `applied` or
+///   `rejected <reason>`;
+/// - `BALANCE <client>`, which is answered with the report for that client;
+/// - `DUMP`, which is answered with the report for every client.
+///
+/// Reports are in the same CSV format as the batch report, and end with an empty line. Empty
+/// lines get no reply.
+pub fn serve(listener: TcpListener, state: State) -> io::Result<()> {
+    let (requests, received) = mpsc::channel();
+    thread::spawn(move || execute(state, received));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("Could not accept a connection: {}", error);
+                continue;
+            }
+        };
+        let requests = requests.clone();
+        thread::spawn(move || {
+            // the connection is closed either way, and there is nobody to tell
+            let _ = handle(stream, requests);
+        });
+    }
+    Ok(())
+}
+
+fn handle(stream: TcpStream, requests: Sender<Request>) -> io::Result<()> {
+    // replies are small, and the client is waiting for each one
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply, replied) = mpsc::channel();
+        if requests.send(Request { line, reply }).is_err() {
+            break;
+        }
+        let Ok(reply) = replied.recv() else {
+            break;
+        };
+        writer.write_all(reply.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Applies requests to the state in the order they are received.
+fn execute(mut state: State, requests: Receiver<Request>) {
+    for Request { line, reply } in requests {
+        // the connection may have gone away since it sent the line, which is fine
+        let _ = reply.send(respond(&mut state, &line));
+    }
+}
+
+fn respond(state: &mut State, line: &str) -> String {
+    let line = line.trim();
+    let report = match line.split_once(' ') {
+        _ if line == "DUMP" => state.serialize_to_csv(),
+        Some(("BALANCE", client)) => match client.trim().parse() {
+            Ok(client) => state.serialize_client_to_csv(client),
+            Err(_) => return "error expected a client id\n".to_string(),
+        },
+        _ => {
+            return match Transaction::from_line(line.as_bytes()) {
+                Some(Ok(transaction)) => match state.try_transact(transaction) {
+                    Ok(()) => "applied\n".to_string(),
+                    Err(error) => format!("rejected {}\n", error),
+                },
+                _ => "rejected could not read the row\n".to_string(),
+            }
+        }
+    };
+    match report {
+        Ok(report) => report + "\n",
+        Err(error) => format!("error {}\n", error),
+    }
+}
+
+#[test]
+fn test_respond() {
+    let mut state = State::default();
+    assert_eq!(respond(&mut state, "deposit,1,1,10"), "applied\n");
+    assert_eq!(
+        respond(&mut state, "dispute,2,1"),
+        "rejected transaction 1 belongs to another client\n"
+    );
+    assert_eq!(
+        respond(&mut state, "deposit,1"),
+        "rejected could not read the row\n"
+    );
+    assert_eq!(respond(&mut state, "deposit,2,2,5"), "applied\n");
+    assert_eq!(
+        respond(&mut state, "BALANCE 2"),
+        "client,available,held,total,locked\n2,5,0,5,false\n\n"
+    );
+    assert_eq!(
+        respond(&mut state, "BALANCE two"),
+        "error expected a client id\n"
+    );
+    assert_eq!(
+        respond(&mut state, "DUMP"),
+        "client,available,held,total,locked\n1,10,0,10,false\n2,5,0,5,false\n\n"
+    );
+}
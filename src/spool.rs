@@ -0,0 +1,453 @@
+use crate::{
+    blocklist::{Blocklist, BlocklistError},
+    input::Compression,
+    State, Transaction,
+};
+use csv::ReaderBuilder;
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// How many rows apart the manifest is written while a file is being applied.
+const MANIFEST_EVERY: u64 = 1000;
+
+/// What the manifest is called in the archive directory.
+const MANIFEST: &str = "manifest.csv";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SpoolError {
+    #[error("could not read the spool: {0}")]
+    Io(#[from] io::Error),
+    #[error("could not read the spool: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("invalid manifest entry on line {line}: {reason}")]
+    InvalidManifest { line: u64, reason: &'static str },
+    #[error("{0} is in the manifest but in neither the spool nor the archive")]
+    Missing(String),
+    #[error(transparent)]
+    Blocklist(#[from] BlocklistError),
+    #[error("{0} is compressed, but the spool only reads uncompressed files")]
+    Compressed(String),
+}
+
+/// How far one file has been applied.
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    /// the byte offset of the first row not yet applied
+    offset: u64,
+    complete: bool,
+}
+
+/// Consumes CSV files dropped into a spool directory, in lexical order of their names. Files
+/// should be moved into the spool once they are complete, and files whose names start with `.`
+/// are ignored. Once a file has been applied it is moved to the archive directory. Progress is
+/// tracked by byte offset, so files must be uncompressed; a compressed one is an error, and is
+/// left in the spool.
+///
+/// Progress is kept in `manifest.csv` in the archive directory, with the columns
+/// `file, offset, complete`, in the order the files were applied. Opening the spool again replays
+/// everything the manifest says was applied, from the archived files and the file that was in
+/// progress, so a restarted process rebuilds its accounts and carries on without applying any row
+/// twice. That means archived files have to be kept for as long as the manifest lists them.
+///
+/// The manifest doesn't record which blocklist each file was applied under, so a replay uses
+/// whatever blocklist `state` has when the spool is opened. If the blocklist has changed since the
+/// rows were first applied, the rebuilt accounts can differ from the ones the process had.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    archive: PathBuf,
+    manifest: Vec<Entry>,
+    /// read again before each file, if given
+    blocklist: Option<PathBuf>,
+}
+
+impl Spool {
+    /// Opens the spool, replaying into `state` everything already applied from it.
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        archive: impl Into<PathBuf>,
+        state: &mut State,
+    ) -> Result<Self, SpoolError> {
+        let mut spool = Spool {
+            dir: dir.into(),
+            archive: archive.into(),
+            manifest: vec![],
+            blocklist: None,
+        };
+        fs::create_dir_all(&spool.archive)?;
+        match File::open(spool.archive.join(MANIFEST)) {
+            Ok(file) => spool.manifest = read_manifest(file)?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+        for entry in &spool.manifest {
+            let path = if entry.complete {
+                // it may not have been moved yet if the process stopped straight after finishing
+                let archived = spool.archive.join(&entry.name);
+                if archived.exists() {
+                    archived
+                } else {
+                    spool.dir.join(&entry.name)
+                }
+            } else {
+                spool.dir.join(&entry.name)
+            };
+            if !path.exists() {
+                return Err(SpoolError::Missing(entry.name.clone()));
+            }
+            apply(&path, 0, Some(entry.offset), state, |_, _| {
+                Ok::<_, SpoolError>(())
+            })?;
+        }
+        for entry in spool.manifest.iter().filter(|entry| entry.complete) {
+            spool.archive_file(&entry.name)?;
+        }
+        Ok(spool)
+    }
+
+    /// Reads the blocklist at `path` into the state before applying each file, so it can be
+    /// updated while the spool is running.
+    pub fn with_blocklist(mut self, path: impl Into<PathBuf>) -> Self {
+        self.blocklist = Some(path.into());
+        self
+    }
+
+    /// Applies every file waiting in the spool, and returns how many rows were read.
+    ///
+    /// `emit` is called with the state every time before the manifest records more progress, so
+    /// that what the rows raised, such as their events and changes, can be written out and taken
+    /// first. A restart then never replays rows whose output was lost, though rows emitted just
+    /// before a crash are emitted again, and only a manifest's worth of output is held at a time.
+    pub fn process<E: From<SpoolError>>(
+        &mut self,
+        state: &mut State,
+        mut emit: impl FnMut(&mut State) -> Result<(), E>,
+    ) -> Result<u64, E> {
+        let mut rows = 0;
+        for name in self.pending().map_err(SpoolError::from)? {
+            self.reload_blocklist(state)?;
+            let index = match self.manifest.iter().position(|entry| entry.name == name) {
+                Some(index) => index,
+                None => {
+                    self.manifest.push(Entry {
+                        name: name.clone(),
+                        offset: 0,
+                        complete: false,
+                    });
+                    self.manifest.len() - 1
+                }
+            };
+            let start = self.manifest[index].offset;
+            let path = self.dir.join(&name);
+            let mut since_written = 0;
+            let end = apply(
+                &path,
+                start,
+                None,
+                state,
+                |state, offset| -> Result<(), E> {
+                    rows += 1;
+                    since_written += 1;
+                    if since_written == MANIFEST_EVERY {
+                        since_written = 0;
+                        emit(state)?;
+                        self.manifest[index].offset = offset;
+                        self.write_manifest().map_err(SpoolError::from)?;
+                    }
+                    Ok(())
+                },
+            )?;
+            emit(state)?;
+            self.manifest[index].offset = end;
+            self.manifest[index].complete = true;
+            // the manifest is written first, so a file is never archived without being complete
+            self.write_manifest().map_err(SpoolError::from)?;
+            self.archive_file(&name).map_err(SpoolError::from)?;
+        }
+        Ok(rows)
+    }
+
+    fn reload_blocklist(&self, state: &mut State) -> Result<(), SpoolError> {
+        if let Some(path) = &self.blocklist {
+            let file = File::open(path).map_err(csv::Error::from)?;
+            state.set_blocklist(Blocklist::from_reader(BufReader::new(file))?);
+        }
+        Ok(())
+    }
+
+    /// The files in the spool which haven't been applied yet, in the order to apply them.
+    fn pending(&self) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        names.sort();
+        // a file that was completed but not archived is archived when the spool is opened
+        names.retain(|name| {
+            !self
+                .manifest
+                .iter()
+                .any(|entry| entry.name == *name && entry.complete)
+        });
+        Ok(names)
+    }
+
+    fn archive_file(&self, name: &str) -> io::Result<()> {
+        let from = self.dir.join(name);
+        if from.exists() {
+            fs::rename(from, self.archive.join(name))?;
+        }
+        Ok(())
+    }
+
+    /// Written alongside and renamed over, so a crash leaves either the old or the new one.
+    fn write_manifest(&self) -> io::Result<()> {
+        let path = self.archive.join(MANIFEST);
+        let partial = self.archive.join(format!(".{}.tmp", MANIFEST));
+        let mut wtr = csv::Writer::from_path(&partial)?;
+        wtr.write_record(["file", "offset", "complete"])?;
+        for entry in &self.manifest {
+            wtr.write_record([
+                entry.name.clone(),
+                entry.offset.to_string(),
+                entry.complete.to_string(),
+            ])?;
+        }
+        wtr.flush()?;
+        let file = wtr.into_inner().map_err(io::Error::other)?;
+        file.sync_data()?;
+        fs::rename(partial, path)
+    }
+}
+
+fn read_manifest(reader: impl io::Read) -> Result<Vec<Entry>, SpoolError> {
+    let mut rdr = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let mut manifest = vec![];
+    for record in rdr.records() {
+        let record = record?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        let invalid = |reason| SpoolError::InvalidManifest { line, reason };
+        manifest.push(Entry {
+            name: record
+                .get(0)
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| invalid("expected a file name"))?
+                .to_string(),
+            offset: record
+                .get(1)
+                .and_then(|offset| offset.parse().ok())
+                .ok_or_else(|| invalid("expected a byte offset"))?,
+            complete: record
+                .get(2)
+                .and_then(|complete| complete.parse().ok())
+                .ok_or_else(|| invalid("expected true or false"))?,
+        });
+    }
+    Ok(manifest)
+}
+
+/// Applies the rows of a file from byte offset `start`, up to `end` if given, calling `applied`
+/// with the state and the offset of the next row after each one. Returns the offset it stopped
+/// at. Rows that can't be read are left out, like they are from any other input, but a compressed
+/// file is an error.
+fn apply<E: From<SpoolError>>(
+    path: &Path,
+    start: u64,
+    end: Option<u64>,
+    state: &mut State,
+    mut applied: impl FnMut(&mut State, u64) -> Result<(), E>,
+) -> Result<u64, E> {
+    let mut file = BufReader::new(File::open(path).map_err(SpoolError::from)?);
+    if Compression::detect(file.fill_buf().map_err(SpoolError::from)?, path) != Compression::None {
+        let name = path.file_name().unwrap_or_default();
+        return Err(SpoolError::Compressed(name.to_string_lossy().into_owned()).into());
+    }
+    file.seek(SeekFrom::Start(start))
+        .map_err(SpoolError::from)?;
+    // the header is only at the start of the file
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(start == 0)
+        .from_reader(file);
+    let mut record = csv::ByteRecord::new();
+    loop {
+        let offset = start + rdr.position().byte();
+        if end.is_some_and(|end| offset >= end) {
+            return Ok(offset);
+        }
+        if !rdr
+            .read_byte_record(&mut record)
+            .map_err(SpoolError::from)?
+        {
+            return Ok(offset);
+        }
+        if let Ok(transaction) = Transaction::from_byte_record(&record) {
+            state.transact(transaction);
+        }
+        applied(state, start + rdr.position().byte())?;
+    }
+}
+
+#[cfg(test)]
+fn emit_nothing(_: &mut State) -> Result<(), SpoolError> {
+    Ok(())
+}
+
+#[test]
+fn test_spool() {
+    let root = std::env::temp_dir().join(format!("spool-{}", std::process::id()));
+    let (dir, archive) = (root.join("spool"), root.join("archive"));
+    fs::create_dir_all(&dir).unwrap();
+    let rows = |client| {
+        let mut rows = "type,client,tx,amount\n".to_string();
+        for tx in 0..MANIFEST_EVERY + 5 {
+            rows += &format!("deposit,{},{},1\n", client, client as u64 * 10_000 + tx);
+        }
+        rows
+    };
+    fs::write(dir.join("2024-01-01T01.csv"), rows(1)).unwrap();
+    fs::write(dir.join("2024-01-01T00.csv"), rows(2)).unwrap();
+    fs::write(dir.join(".partial.csv"), rows(3)).unwrap();
+
+    let mut state = State::default();
+    let mut spool = Spool::open(&dir, &archive, &mut state).unwrap();
+    assert_eq!(
+        spool.process(&mut state, emit_nothing).unwrap(),
+        2 * (MANIFEST_EVERY + 5)
+    );
+    assert!(archive.join("2024-01-01T00.csv").exists());
+    assert!(!dir.join("2024-01-01T01.csv").exists());
+    let manifest = fs::read_to_string(archive.join(MANIFEST)).unwrap();
+    assert!(manifest.starts_with("file,offset,complete\n2024-01-01T00.csv,"));
+
+    // as if the process stopped partway through the next file
+    fs::write(dir.join("2024-01-01T02.csv"), rows(4)).unwrap();
+    spool.manifest.push(Entry {
+        name: "2024-01-01T02.csv".to_string(),
+        offset: 0,
+        complete: false,
+    });
+    let path = dir.join("2024-01-01T02.csv");
+    let mut applied = 0;
+    let offset = apply(&path, 0, Some(100), &mut state, |_, _| {
+        applied += 1;
+        Ok::<_, SpoolError>(())
+    })
+    .unwrap();
+    spool.manifest[2].offset = offset;
+    spool.write_manifest().unwrap();
+    drop(spool);
+
+    let mut restarted = State::default();
+    let mut spool = Spool::open(&dir, &archive, &mut restarted).unwrap();
+    assert_eq!(
+        spool.process(&mut restarted, emit_nothing).unwrap(),
+        MANIFEST_EVERY + 5 - applied
+    );
+    let mut expected = State::default();
+    for client in [2, 1, 4] {
+        let rows = rows(client);
+        let mut rdr = ReaderBuilder::new().from_reader(rows.as_bytes());
+        for transaction in rdr.deserialize() {
+            expected.transact(transaction.unwrap());
+        }
+    }
+    assert_eq!(
+        restarted.serialize_to_csv().unwrap(),
+        expected.serialize_to_csv().unwrap()
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_spool_blocklist() {
+    let root = std::env::temp_dir().join(format!("spool-blocklist-{}", std::process::id()));
+    let (dir, archive) = (root.join("spool"), root.join("archive"));
+    fs::create_dir_all(&dir).unwrap();
+    let blocklist = root.join("blocklist.csv");
+    fs::write(&blocklist, "client,reason\n").unwrap();
+
+    let mut state = State::default();
+    let mut spool = Spool::open(&dir, &archive, &mut state)
+        .unwrap()
+        .with_blocklist(&blocklist);
+    fs::write(dir.join("a.csv"), "type,client,tx,amount\ndeposit,1,1,5\n").unwrap();
+    spool.process(&mut state, emit_nothing).unwrap();
+
+    // the next file is applied under the updated blocklist
+    fs::write(&blocklist, "client,reason\n1,fraud\n").unwrap();
+    fs::write(dir.join("b.csv"), "type,client,tx,amount\ndeposit,1,2,5\n").unwrap();
+    spool.process(&mut state, emit_nothing).unwrap();
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,5,0,5,false\n"
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_spool_compressed() {
+    use std::io::Write;
+
+    let root = std::env::temp_dir().join(format!("spool-compressed-{}", std::process::id()));
+    let (dir, archive) = (root.join("spool"), root.join("archive"));
+    fs::create_dir_all(&dir).unwrap();
+    let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gzip.write_all(b"type,client,tx,amount\ndeposit,1,1,5\n")
+        .unwrap();
+    fs::write(dir.join("a.csv"), gzip.finish().unwrap()).unwrap();
+
+    let mut state = State::default();
+    let mut spool = Spool::open(&dir, &archive, &mut state).unwrap();
+    assert!(matches!(
+        spool.process(&mut state, emit_nothing),
+        Err(SpoolError::Compressed(name)) if name == "a.csv"
+    ));
+    assert!(dir.join("a.csv").exists());
+    assert!(!archive.join("a.csv").exists());
+    assert!(!archive.join(MANIFEST).exists());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_spool_emit() {
+    let root = std::env::temp_dir().join(format!("spool-emit-{}", std::process::id()));
+    let (dir, archive) = (root.join("spool"), root.join("archive"));
+    fs::create_dir_all(&dir).unwrap();
+    let mut rows = "type,client,tx,amount\n".to_string();
+    for tx in 0..MANIFEST_EVERY * 2 + 5 {
+        rows += &format!("deposit,1,{},1\n", tx);
+    }
+    fs::write(dir.join("a.csv"), rows).unwrap();
+
+    let mut state = State::default().with_changes();
+    let mut spool = Spool::open(&dir, &archive, &mut state).unwrap();
+    let mut emitted = vec![];
+    spool
+        .process(&mut state, |state| {
+            // the manifest doesn't yet cover the rows being emitted
+            let recorded = fs::read_to_string(archive.join(MANIFEST)).unwrap_or_default();
+            emitted.push((state.take_changes().len() as u64, recorded.lines().count()));
+            Ok::<_, SpoolError>(())
+        })
+        .unwrap();
+    assert_eq!(
+        emitted,
+        vec![(MANIFEST_EVERY, 0), (MANIFEST_EVERY, 2), (5, 2)]
+    );
+
+    fs::remove_dir_all(&root).unwrap();
+}
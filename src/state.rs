@@ -1,12 +1,44 @@
-use crate::{ClientId, Transaction, TxId};
+use crate::{
+    accrual::AccrualConfig,
+    blocklist::Blocklist,
+    fx::{Conversion, RateTable},
+    rules::{Outcome, RuleSet},
+    AccountId, ClientId, Currency, Date, Timestamp, Transaction, TxId, MAIN_ACCOUNT,
+};
 use fnv::FnvHashMap;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+mod change;
+pub use change::{Balance, Cause, Change};
+
+mod checkpoint;
+pub use checkpoint::AsOf;
+use checkpoint::Checkpoints;
+
+mod clock;
+pub use clock::{Clock, Window};
+
+mod event;
+pub use event::Event;
+
+mod ledger;
+use ledger::{Ledger, LedgerAccount::*};
+pub use ledger::{LedgerAccount, TrialBalance};
+
+mod error;
+pub use error::TransactionError;
+
+mod lock;
+pub use lock::{LockPolicy, LockReason, LockScope, LockedAccountPolicy};
+
+mod report;
+pub use report::{AccountReport, DisputeStatus, TransactionStatus};
 
 mod processed_transaction;
 use processed_transaction::ProcessedTransaction;
 
 /// The state of all accounts in the system.
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct State {
     client_accounts: HashMap<ClientId, ClientAccount>,
     // in the case of disputes, we need to find a transaction by ID. Therefore, we want to
@@ -28,17 +60,100 @@ pub struct State {
     //
     // In real life this would be a database with an index on the primary key, anyway.
     processed_txns: FnvHashMap<TxId, ProcessedTransaction>,
+    // Every balance lives in the ledger, including the client balances. Storing `held` and `total`
+    // on the `ClientAccount` as well would give us two sources of truth, so the account only
+    // tracks what the ledger can't.
+    ledger: Ledger,
+    /// Authorizations expire against the number of rows in the clock.
+    clock: Clock,
+    /// How far, in milliseconds, a row's timestamp may fall behind the clock before the row is
+    /// rejected.
+    clock_tolerance: u64,
+    /// How many rows an authorization may stay pending before its funds are released. `None`
+    /// means authorizations never expire.
+    authorization_expiry: Option<u64>,
+    // Every authorization has the same lifetime, so they expire in the order they were created
+    // and a queue is all that is needed to find the expired ones. Entries are the row at which the
+    // authorization expires, and the authorization itself. Captured or voided authorizations are
+    // left in the queue and skipped when they reach the front.
+    pending_authorizations: VecDeque<(u64, TxId)>,
+    /// If set, every balance is kept in this base currency, and foreign-currency transactions are
+    /// converted into it using the rate table.
+    fx: Option<(Currency, RateTable)>,
+    accrual: AccrualConfig,
+    /// The end of the most recent accrual period. The next period starts here.
+    last_accrual: Option<Date>,
+    dispute_policy: DisputePolicy,
+    // Like authorizations, every dispute has the same lifetime, so they expire in the order they
+    // were opened. Entries are the disputed transaction and when the dispute was opened. Disputes
+    // which were resolved, charged back, or reopened are skipped when they reach the front.
+    pending_disputes: VecDeque<(TxId, Clock)>,
+    /// Events that have happened since they were last taken.
+    events: Vec<Event>,
+    /// Changes made by rows since they were last taken. `None` unless changes are being recorded.
+    changes: Option<Vec<Change>>,
+    /// Rules that every row is checked against before it is applied.
+    rules: RuleSet,
+    /// Clients who may not deposit or withdraw.
+    blocklist: Blocklist,
+    lock_policy: LockPolicy,
+    /// Whether locking applies to the whole client or just the wallet involved.
+    lock_scope: LockScope,
+    locked_account_policy: LockedAccountPolicy,
+    /// If set, earlier states are kept so that balances can be reported as of an earlier point.
+    checkpoints: Option<Checkpoints>,
 }
 
-/// Represents the state of a specific account for a given client.
-#[derive(Default, Debug)]
+/// Limits on when disputes can be opened and how long they may stay open. The default places no
+/// limits on either.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisputePolicy {
+    /// How long after a transaction was recorded it may still be disputed.
+    pub filing_window: Option<Window>,
+    /// How long a dispute may stay open before it is resolved in the client's favour.
+    pub expiry: Option<Window>,
+}
+
+/// An amount as it will be written to the ledger, after any currency conversion.
+struct Booked {
+    amount: f64,
+    currency: Option<Currency>,
+    conversion: Option<Conversion>,
+}
+
+/// Represents the state of a specific account for a given client. Balances are looked up in the
+/// [Ledger] under [LedgerAccount::ClientAvailable] and [LedgerAccount::ClientHeld], for each of
+/// the client's wallets.
+#[derive(Default, Debug, Clone)]
 struct ClientAccount {
-    /// total amount of funds currently held in dispute
-    held: f64,
-    /// total amount of funds in this account
-    total: f64,
-    /// whether or not the client account is frozen
-    locked: bool,
+    /// why the client account is frozen, if it is
+    lock_reason: Option<LockReason>,
+    /// wallets which are frozen on their own, and why
+    locked_wallets: FnvHashMap<AccountId, LockReason>,
+    /// how many deposits and chargebacks the client has had, for the [LockPolicy]
+    deposits: u64,
+    chargebacks: u64,
+}
+
+impl ClientAccount {
+    /// Why the wallet is frozen, if it is. Locking the whole account freezes every wallet.
+    fn lock_reason(&self, account: AccountId) -> Option<LockReason> {
+        self.lock_reason
+            .or_else(|| self.locked_wallets.get(&account).copied())
+    }
+
+    /// Locks the whole account, or just one wallet, unless it is already locked, in which case
+    /// the original reason stands.
+    fn lock(&mut self, scope: LockScope, account: AccountId, reason: LockReason) {
+        match scope {
+            LockScope::Client => {
+                self.lock_reason.get_or_insert(reason);
+            }
+            LockScope::Wallet => {
+                self.locked_wallets.entry(account).or_insert(reason);
+            }
+        }
+    }
 }
 
 impl State {
@@ -46,140 +161,1300 @@ impl State {
     // As there is no desire to report invalid transactions back to the user, this function is
     // infallible.
     pub fn transact(&mut self, transaction: Transaction) {
-        match transaction {
-            Transaction::Deposit { client, tx, amount } => {
-                self.deposit(client, amount);
-                self.insert_processed_deposit(client, amount, tx);
-            }
-            Transaction::Withdrawal { client, tx, amount } => {
-                self.withdraw(client, amount);
-                self.insert_processed_withdrawal(client, amount, tx);
-            }
-            Transaction::Dispute { client, tx } => self.dispute(client, tx),
-            Transaction::Resolve { client, tx } => self.resolve(client, tx),
-            Transaction::Chargeback { client, tx } => self.chargeback(client, tx),
-            Transaction::Unrecognized(_) => (),
+        let _ = self.try_transact(transaction);
+    }
+
+    /// Like [State::transact], but says why a transaction was not applied.
+    pub fn try_transact(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let Some(mut checkpoints) = self.checkpoints.take() else {
+            return self.observe(transaction);
         };
+        if checkpoints.is_due(self.clock) {
+            checkpoints.push_snapshot(self.clock, self.clone());
+        }
+        let result = self.observe(transaction.clone());
+        checkpoints.record(self.clock, transaction);
+        self.checkpoints = Some(checkpoints);
+        result
     }
 
-    pub fn withdraw(&mut self, client: ClientId, amount: f64) {
-        let client = self.get_client(client);
+    /// Applies the transaction, recording the change it made if changes are being recorded.
+    /// Anything which expires by the row's time is expired first, and recorded as its own change,
+    /// so the row's change starts from the balances it was actually applied to.
+    fn observe(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let advanced = self.advance(transaction.timestamp());
+        let wallet = transaction.account();
+        let (Some(_), Some((client, account))) = (&self.changes, wallet) else {
+            return advanced.and_then(|()| self.apply(transaction));
+        };
+        let currency = self.change_currency(&transaction);
+        let before = self.balance(client, account, currency);
+        let result = advanced.and_then(|()| self.apply(transaction.clone()));
+        self.record_change(
+            Cause::Row(transaction),
+            (client, account, currency),
+            before,
+            result.clone(),
+        );
+        result
+    }
 
-        if client.locked {
-            return;
+    /// Records a change to a wallet, whose balances were `before` until now, if changes are being
+    /// recorded.
+    fn record_change(
+        &mut self,
+        cause: Cause,
+        (client, account, currency): (ClientId, AccountId, Option<Currency>),
+        before: Balance,
+        result: Result<(), TransactionError>,
+    ) {
+        let after = self.balance(client, account, currency);
+        if let Some(changes) = &mut self.changes {
+            changes.push(Change {
+                cause,
+                client,
+                account,
+                at: self.clock,
+                currency,
+                result,
+                before,
+                after,
+            });
         }
+    }
 
-        client.total -= amount;
+    /// A wallet's balances, if changes are being recorded and will need them.
+    fn balance_for_change(
+        &self,
+        client: ClientId,
+        account: AccountId,
+        currency: Option<Currency>,
+    ) -> Option<Balance> {
+        self.changes
+            .is_some()
+            .then(|| self.balance(client, account, currency))
     }
 
-    pub fn deposit(&mut self, client: ClientId, amount: f64) {
-        let client = self.get_client(client);
+    /// The currency a row's balances are reported in, worked out before the row is applied.
+    fn change_currency(&self, transaction: &Transaction) -> Option<Currency> {
+        let own = match transaction {
+            Transaction::Deposit { currency, .. }
+            | Transaction::Withdrawal { currency, .. }
+            | Transaction::Authorize { currency, .. } => *currency,
+            Transaction::Refund {
+                currency, original, ..
+            } => currency.or_else(|| {
+                self.processed_txns
+                    .get(original)
+                    .and_then(ProcessedTransaction::currency)
+            }),
+            _ => transaction
+                .tx()
+                .and_then(|tx| self.processed_txns.get(&tx))
+                .and_then(ProcessedTransaction::currency),
+        };
+        self.reported_currency(own)
+    }
 
-        if client.locked {
-            return;
+    /// The currency a change in `own` currency is reported in.
+    fn reported_currency(&self, own: Option<Currency>) -> Option<Currency> {
+        // foreign currencies are booked in the base currency
+        match &self.fx {
+            Some((base, _)) => Some(*base),
+            None => own,
         }
+    }
 
-        client.total += amount;
+    fn balance(&self, id: ClientId, account: AccountId, currency: Option<Currency>) -> Balance {
+        let available = self.ledger.balance(ClientAvailable(id, account), currency);
+        let held = self.ledger.balance(ClientHeld(id, account), currency);
+        Balance {
+            held,
+            total: available + held,
+            locked: self.is_locked(id, account),
+        }
     }
 
-    pub fn dispute(&mut self, client_id: ClientId, tx: TxId) {
-        if let Some(ref mut processed_txn) = self.processed_txns.get_mut(&tx) {
-            // if the client ids don't match, the input is malformed.
-            if processed_txn.client_id() != client_id {
-                return;
+    /// Moves the clock on to a row with `timestamp`, and expires whatever has been pending for too
+    /// long by then. Fails if the row is too late to be applied.
+    fn advance(&mut self, timestamp: Option<Timestamp>) -> Result<(), TransactionError> {
+        if !self.clock.tick(timestamp, self.clock_tolerance) {
+            return Err(TransactionError::Late);
+        }
+        self.expire_authorizations();
+        self.expire_disputes();
+        Ok(())
+    }
+
+    /// Applies a row the clock has already been moved on to.
+    fn apply(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        if let Transaction::Deposit { client, .. }
+        | Transaction::Withdrawal { client, .. }
+        | Transaction::Authorize { client, .. } = transaction
+        {
+            if let Some(reason) = self.blocklist.reason(client) {
+                return Err(TransactionError::Blocked {
+                    client,
+                    reason: reason.to_string(),
+                });
             }
+        }
 
-            processed_txn.set_disputed(true);
+        // a second transaction with the same id would replace the first, which could then no
+        // longer be disputed or refunded
+        if let Transaction::Deposit { tx, .. }
+        | Transaction::Withdrawal { tx, .. }
+        | Transaction::Authorize { tx, .. }
+        | Transaction::Refund { tx, .. } = transaction
+        {
+            if self.processed_txns.contains_key(&tx) {
+                return Err(TransactionError::DuplicateTransaction(tx));
+            }
+        }
 
-            // From how I understand the problem, we only want to hold funds if it is
-            // a deposit? pending my email question
-            let tx_amount = processed_txn.amount();
-            if processed_txn.is_deposit() {
-                let client = self.get_client(client_id);
-                client.held += tx_amount;
+        // the rules only need to remember rows that were applied, so keep a copy until we know
+        let checked = (!self.rules.is_empty()).then(|| transaction.clone());
+        if let Some(ref checked) = checked {
+            self.check_applicable(checked)?;
+            self.check_rules(checked)?;
+        }
+
+        let result = match transaction {
+            Transaction::Deposit {
+                client,
+                account,
+                tx,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                let booked = self.book(amount, currency, timestamp)?;
+                self.deposit(client, account, booked.amount, booked.currency)?;
+                self.insert_processed_deposit(client, account, booked, tx, timestamp);
+                Ok(())
+            }
+            Transaction::Withdrawal {
+                client,
+                account,
+                tx,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                let booked = self.book(amount, currency, timestamp)?;
+                self.withdraw(client, account, booked.amount, booked.currency)?;
+                self.insert_processed_withdrawal(client, account, booked, tx, timestamp);
+                Ok(())
+            }
+            Transaction::Dispute {
+                client,
+                account,
+                tx,
+                ..
+            } => self.dispute(client, account, tx),
+            Transaction::Resolve {
+                client,
+                account,
+                tx,
+                ..
+            } => self.resolve(client, account, tx),
+            Transaction::Chargeback {
+                client,
+                account,
+                tx,
+                ..
+            } => self.chargeback(client, account, tx),
+            Transaction::Authorize {
+                client,
+                account,
+                tx,
+                amount,
+                currency,
+                timestamp,
+            } => {
+                let booked = self.book(amount, currency, timestamp)?;
+                self.authorize(client, account, tx, booked, timestamp)
             }
+            Transaction::Capture {
+                client,
+                account,
+                tx,
+                amount,
+                ..
+            } => self.capture(client, account, tx, amount),
+            Transaction::Void {
+                client,
+                account,
+                tx,
+                ..
+            } => self.void(client, account, tx),
+            Transaction::Refund {
+                client,
+                account,
+                tx,
+                amount,
+                currency,
+                original,
+                timestamp,
+            } => self.refund(client, account, tx, amount, currency, original, timestamp),
+            Transaction::Accrue { date, .. } => {
+                self.accrue(date);
+                Ok(())
+            }
+            Transaction::Unrecognized(r#type) => Err(TransactionError::Unrecognized(r#type)),
         };
+        if let (Ok(()), Some(checked)) = (&result, checked) {
+            self.rules.record(&checked, self.clock);
+        }
+        result
     }
 
-    fn get_client(&mut self, id: ClientId) -> &mut ClientAccount {
-        self.client_accounts
-            .entry(id)
-            .or_insert_with(Default::default)
+    /// Rejects a row the rules apply to if it would be rejected anyway, so that a row which
+    /// changes nothing can't raise an alert or lock an account.
+    fn check_applicable(&self, transaction: &Transaction) -> Result<(), TransactionError> {
+        match *transaction {
+            Transaction::Deposit {
+                client,
+                account,
+                amount,
+                currency,
+                timestamp,
+                ..
+            } => {
+                self.book(amount, currency, timestamp)?;
+                if self.locked_out(client, account, |allowed| allowed.deposit) {
+                    return Err(TransactionError::Locked(client));
+                }
+                Ok(())
+            }
+            Transaction::Withdrawal {
+                client,
+                account,
+                amount,
+                currency,
+                timestamp,
+                ..
+            } => {
+                self.book(amount, currency, timestamp)?;
+                if self.locked_out(client, account, |allowed| allowed.withdrawal) {
+                    return Err(TransactionError::Locked(client));
+                }
+                Ok(())
+            }
+            Transaction::Dispute {
+                client,
+                account,
+                tx,
+                ..
+            } => self.check_dispute(client, account, tx),
+            _ => Ok(()),
+        }
     }
 
-    pub fn resolve(&mut self, client_id: ClientId, tx: TxId) {
-        if let Some(ref mut tx) = self.processed_txns.get_mut(&tx) {
-            if tx.client_id() != client_id {
+    /// Raises an alert for every rule the row breaks, and acts on the most severe outcome.
+    fn check_rules(&mut self, transaction: &Transaction) -> Result<(), TransactionError> {
+        let Some((client, account)) = transaction.account() else {
+            return Ok(());
+        };
+        let mut worst: Option<(Outcome, &'static str)> = None;
+        for (rule, outcome) in self.rules.check(transaction, self.clock) {
+            if outcome != Outcome::Allow {
+                self.events.push(Event::RuleBroken {
+                    client,
+                    tx: transaction.tx(),
+                    rule: rule.name(),
+                    outcome,
+                    at: self.clock,
+                });
+            }
+            worst = worst.max(Some((outcome, rule.name())));
+        }
+        match worst {
+            Some((Outcome::Reject, rule)) => Err(TransactionError::Rule(rule)),
+            Some((Outcome::Lock, rule)) => {
+                let scope = self.lock_scope;
+                self.get_client(client)
+                    .lock(scope, account, LockReason::Rule(rule));
+                Err(TransactionError::Rule(rule))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets how far, in milliseconds, a row's timestamp may fall behind the latest timestamp seen
+    /// before the row is rejected. The default is zero, so timestamps must never go backwards.
+    pub fn with_clock_tolerance(mut self, millis: u64) -> Self {
+        self.clock_tolerance = millis;
+        self
+    }
+
+    /// How much input this state has seen, and how far along in time it is.
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Sets how many rows an authorization may stay pending before it expires and its funds are
+    /// released back to the client.
+    pub fn with_authorization_expiry(mut self, rows: u64) -> Self {
+        self.authorization_expiry = Some(rows);
+        self
+    }
+
+    /// Keeps a copy of the state every `rows` rows, and every row since the first copy, so that
+    /// [State::as_of] can rebuild earlier states without replaying more than `rows` rows. Copies
+    /// are only taken while processing, so this should be set before the first row. Every copy is
+    /// kept, so memory grows with the input times the number of copies; when the point to report
+    /// at is known up front, [State::with_checkpoints_for] keeps far less.
+    pub fn with_checkpoints(mut self, rows: u64) -> Self {
+        self.checkpoints = Some(Checkpoints::new(rows, None));
+        self
+    }
+
+    /// Like [State::with_checkpoints], but only keeps what is needed to rebuild the state as of
+    /// `as_of`: a single copy, and at most `rows` rows after it. Nothing is kept once `as_of` has
+    /// been passed, and [State::as_of] can only rebuild `as_of` itself.
+    pub fn with_checkpoints_for(mut self, rows: u64, as_of: AsOf) -> Self {
+        self.checkpoints = Some(Checkpoints::new(rows, Some(as_of)));
+        self
+    }
+
+    /// The state as it was at an earlier point in the input, which can then be reported on. If
+    /// `as_of` is after the latest row, or checkpoints weren't enabled with
+    /// [State::with_checkpoints], this is the current state.
+    pub fn as_of(&self, as_of: AsOf) -> State {
+        match &self.checkpoints {
+            Some(checkpoints) => checkpoints.rebuild(as_of).unwrap_or_default(),
+            None => self.clone(),
+        }
+    }
+
+    /// Checks every row against `rules` before applying it. Rows which break a rule may be
+    /// flagged, rejected, or have the client's account locked, and an [Event::RuleBroken] is
+    /// raised for each.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Rejects deposits, withdrawals, and authorizations from the clients in `blocklist`. Their
+    /// disputes, resolutions, and chargebacks still go ahead.
+    pub fn with_blocklist(mut self, blocklist: Blocklist) -> Self {
+        self.blocklist = blocklist;
+        self
+    }
+
+    /// Replaces the blocklist, for example between input files in a long-running process.
+    pub fn set_blocklist(&mut self, blocklist: Blocklist) {
+        self.blocklist = blocklist;
+    }
+
+    /// Sets when a chargeback locks the client's account.
+    pub fn with_lock_policy(mut self, policy: LockPolicy) -> Self {
+        self.lock_policy = policy;
+        self
+    }
+
+    /// Sets whether a lock applies to all of a client's wallets, or only the one involved.
+    pub fn with_lock_scope(mut self, scope: LockScope) -> Self {
+        self.lock_scope = scope;
+        self
+    }
+
+    /// Sets which transactions a client may still make once their account is locked.
+    pub fn with_locked_account_policy(mut self, policy: LockedAccountPolicy) -> Self {
+        self.locked_account_policy = policy;
+        self
+    }
+
+    /// Sets when disputes may be opened and how long they may stay open.
+    pub fn with_dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.dispute_policy = policy;
+        self
+    }
+
+    /// Removes and returns every event that has happened since the last call.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Records the [Change] every row for a client makes, whether or not it is applied.
+    pub fn with_changes(mut self) -> Self {
+        self.changes = Some(vec![]);
+        self
+    }
+
+    /// Takes the changes recorded since they were last taken. Always empty unless
+    /// [State::with_changes] was used.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        self.changes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Keeps all balances in `base_currency`, converting any transaction in another currency using
+    /// the most recent rate in `rates`. Transactions without a currency are assumed to be in the
+    /// base currency, and transactions in a currency with no known rate are ignored.
+    pub fn with_fx(mut self, base_currency: Currency, rates: RateTable) -> Self {
+        self.fx = Some((base_currency, rates));
+        self
+    }
+
+    /// Sets the interest rates and fees used when accruing.
+    pub fn with_accrual(mut self, config: AccrualConfig) -> Self {
+        self.accrual = config;
+        self
+    }
+
+    /// Accrues interest and fees on every balance for the period since the last accrual, ending
+    /// on `date`. Interest is computed on each balance's available funds, so held funds earn
+    /// nothing. The first accrual only marks the start of the first period, and accruals dated
+    /// before the end of the previous period are ignored.
+    pub fn accrue(&mut self, date: Date) {
+        let start = match self.last_accrual {
+            Some(start) if start < date => start,
+            Some(_) => return,
+            None => {
+                self.last_accrual = Some(date);
                 return;
             }
-            tx.set_disputed(false);
+        };
+        self.last_accrual = Some(date);
+        let year_fraction = self.accrual.day_count.year_fraction(start, date);
 
-            let tx_amount = tx.amount();
+        let balances = self.ledger.client_balances().collect::<BTreeSet<_>>();
+        for (client_id, account, currency) in balances {
+            let before = self.balance_for_change(client_id, account, currency);
+            let available = self
+                .ledger
+                .balance(ClientAvailable(client_id, account), currency);
+            let interest = self.accrual.interest(available, year_fraction);
+            if interest > 0. {
+                self.ledger.transfer(
+                    currency,
+                    InterestExpense,
+                    ClientAvailable(client_id, account),
+                    interest,
+                );
+            } else if interest < 0. {
+                self.ledger.transfer(
+                    currency,
+                    ClientAvailable(client_id, account),
+                    InterestIncome,
+                    -interest,
+                );
+            }
+            if self.accrual.period_fee > 0. {
+                self.ledger.transfer(
+                    currency,
+                    ClientAvailable(client_id, account),
+                    Fees,
+                    self.accrual.period_fee,
+                );
+            }
+            if let Some(before) = before.filter(|_| interest != 0. || self.accrual.period_fee > 0.)
+            {
+                self.record_change(
+                    Cause::Accrual,
+                    (client_id, account, currency),
+                    before,
+                    Ok(()),
+                );
+            }
+        }
+    }
 
-            if tx.is_deposit() {
-                let mut client = self.get_client(client_id);
-                client.held -= tx_amount;
+    /// Works out how an incoming amount should be booked. A foreign amount is converted at the
+    /// rate for the date the row happened; a row without a timestamp is taken to have happened at
+    /// the latest time seen, and before any timestamp has been seen the newest rate is used. Fails
+    /// if it has to be converted and there is no rate to do so with.
+    fn book(
+        &self,
+        amount: f64,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
+    ) -> Result<Booked, TransactionError> {
+        let (base, rates) = match &self.fx {
+            Some((base, rates)) => (*base, rates),
+            None => {
+                return Ok(Booked {
+                    amount,
+                    currency,
+                    conversion: None,
+                })
             }
+        };
+        let conversion = match currency {
+            Some(from) if from != base => Some(Conversion {
+                from,
+                original_amount: amount,
+                rate: rates
+                    .rate(
+                        from,
+                        base,
+                        self.recorded_at(timestamp).time.map(|time| time.date()),
+                    )
+                    .ok_or(TransactionError::NoRate(from))?,
+            }),
+            _ => None,
+        };
+        Ok(Booked {
+            amount: conversion.map_or(amount, |conversion| conversion.apply(amount)),
+            currency: Some(base),
+            conversion,
+        })
+    }
+
+    pub fn withdraw(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactionError> {
+        if self.locked_out(client_id, account, |allowed| allowed.withdrawal) {
+            return Err(TransactionError::Locked(client_id));
         }
+        self.get_client(client_id);
+
+        self.ledger.transfer(
+            currency,
+            ClientAvailable(client_id, account),
+            Settlement,
+            amount,
+        );
+        Ok(())
+    }
+
+    pub fn deposit(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        amount: f64,
+        currency: Option<Currency>,
+    ) -> Result<(), TransactionError> {
+        if self.locked_out(client_id, account, |allowed| allowed.deposit) {
+            return Err(TransactionError::Locked(client_id));
+        }
+        let client = self.get_client(client_id);
+        client.deposits += 1;
+
+        self.ledger.transfer(
+            currency,
+            Settlement,
+            ClientAvailable(client_id, account),
+            amount,
+        );
+        Ok(())
+    }
+
+    /// Why a dispute of `tx` would be rejected, if it would be.
+    fn check_dispute(
+        &self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.dispute);
+        let processed_txn = self
+            .processed_txns
+            .get(&tx)
+            .ok_or(TransactionError::UnknownTransaction(tx))?;
+        // if the client ids don't match, the input is malformed.
+        if processed_txn.client_id() != client_id {
+            return Err(TransactionError::WrongClient(tx));
+        }
+        if processed_txn.account() != account {
+            return Err(TransactionError::WrongAccount(tx));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+
+        if !processed_txn.is_disputable() {
+            return Err(TransactionError::NotDisputable(tx));
+        }
+        // holding the funds a second time would hold more than the transaction was for
+        if processed_txn.is_charged_back() {
+            return Err(TransactionError::ChargedBack(tx));
+        }
+        if processed_txn.is_disputed() {
+            return Err(TransactionError::AlreadyDisputed(tx));
+        }
+        // there is nothing left to dispute
+        if processed_txn.is_refunded_in_full() {
+            return Err(TransactionError::Refunded(tx));
+        }
+
+        if let Some(window) = self.dispute_policy.filing_window {
+            if self.clock.has_elapsed(processed_txn.recorded_at(), window) {
+                return Err(TransactionError::FilingWindowClosed(tx));
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a dispute on an earlier transaction. If the dispute policy has a filing window,
+    /// transactions recorded longer ago than that can no longer be disputed. Only the part of the
+    /// transaction which hasn't been refunded is disputed, so one refunded in full can't be.
+    pub fn dispute(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+    ) -> Result<(), TransactionError> {
+        self.check_dispute(client_id, account, tx)?;
+        let processed_txn = self
+            .processed_txns
+            .get_mut(&tx)
+            .expect("the dispute was checked");
+        processed_txn.set_disputed(true);
+        processed_txn.set_disputed_at(self.clock);
+        if self.dispute_policy.expiry.is_some() {
+            self.pending_disputes.push_back((tx, self.clock));
+        }
+
+        // From how I understand the problem, we only want to hold funds if it is
+        // a deposit? pending my email question
+        let tx_amount = processed_txn.disputable_amount();
+        let currency = processed_txn.currency();
+        if processed_txn.credits_client() {
+            self.get_client(client_id);
+            self.ledger.transfer(
+                currency,
+                ClientAvailable(client_id, account),
+                ClientHeld(client_id, account),
+                tx_amount,
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether the client's account is locked, and the locked account policy doesn't allow what
+    /// they are trying to do.
+    fn locked_out(
+        &self,
+        id: ClientId,
+        account: AccountId,
+        allowed: impl Fn(&LockedAccountPolicy) -> bool,
+    ) -> bool {
+        self.is_locked(id, account) && !allowed(&self.locked_account_policy)
+    }
+
+    fn is_locked(&self, id: ClientId, account: AccountId) -> bool {
+        self.lock_reason(id, account).is_some()
+    }
+
+    fn lock_reason(&self, id: ClientId, account: AccountId) -> Option<LockReason> {
+        self.client_accounts
+            .get(&id)
+            .and_then(|client| client.lock_reason(account))
+    }
+
+    fn get_client(&mut self, id: ClientId) -> &mut ClientAccount {
+        self.client_accounts.entry(id).or_default()
+    }
+
+    pub fn resolve(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.resolve);
+        let processed_txn = self
+            .processed_txns
+            .get_mut(&tx)
+            .ok_or(TransactionError::UnknownTransaction(tx))?;
+        if processed_txn.client_id() != client_id {
+            return Err(TransactionError::WrongClient(tx));
+        }
+        if processed_txn.account() != account {
+            return Err(TransactionError::WrongAccount(tx));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+        if !processed_txn.is_disputed() {
+            return Err(TransactionError::NotDisputed(tx));
+        }
+        processed_txn.set_disputed(false);
+
+        let tx_amount = processed_txn.disputable_amount();
+        let currency = processed_txn.currency();
+
+        if processed_txn.credits_client() {
+            self.get_client(client_id);
+            self.ledger.transfer(
+                currency,
+                ClientHeld(client_id, account),
+                ClientAvailable(client_id, account),
+                tx_amount,
+            );
+        }
+        Ok(())
     }
     /// Processes a chargeback request, which takes a disputed transaction and reverts it. In the case
-    /// of a deposit, remove the funds. In the case of a withdrawal, add the funds back.
-    pub fn chargeback(&mut self, client: ClientId, tx: TxId) {
-        if let Some(tx) = self.processed_txns.get(&tx) {
-            if tx.client_id() != client {
-                return;
+    /// of a deposit, the held funds go back out to wherever they came from. In the case of a
+    /// withdrawal, the funds are added back, and the system eats the loss. Refunds are reverted
+    /// like whichever of the two they resemble. Only the part which hadn't been refunded is
+    /// reverted. Whether the client's account is locked depends on
+    /// the [LockPolicy].
+    pub fn chargeback(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.chargeback);
+        let processed_txn = self
+            .processed_txns
+            .get_mut(&tx)
+            .ok_or(TransactionError::UnknownTransaction(tx))?;
+        if processed_txn.client_id() != client_id {
+            return Err(TransactionError::WrongClient(tx));
+        }
+        if processed_txn.account() != account {
+            return Err(TransactionError::WrongAccount(tx));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+        if processed_txn.is_charged_back() {
+            return Err(TransactionError::ChargedBack(tx));
+        }
+        if !processed_txn.is_disputed() {
+            // disallow chargebacks on transactions that haven't been disputed
+            return Err(TransactionError::NotDisputed(tx));
+        }
+        let tx_amount = processed_txn.disputable_amount();
+        let currency = processed_txn.currency();
+        let tx_credited_client = processed_txn.credits_client();
+        processed_txn.set_charged_back();
+        let (lock_policy, lock_scope) = (self.lock_policy, self.lock_scope);
+        let client = self.get_client(client_id);
+        client.chargebacks += 1;
+        if lock_policy.should_lock(tx_credited_client, client.chargebacks, client.deposits) {
+            client.lock(lock_scope, account, LockReason::Chargeback(tx));
+        }
+        if tx_credited_client {
+            self.ledger.transfer(
+                currency,
+                ClientHeld(client_id, account),
+                Settlement,
+                tx_amount,
+            );
+        } else {
+            self.ledger.transfer(
+                currency,
+                ChargebackLoss,
+                ClientAvailable(client_id, account),
+                tx_amount,
+            );
+        }
+        Ok(())
+    }
+
+    /// Reserves the client's available funds by moving them into held.
+    fn authorize(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+        booked: Booked,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionError> {
+        if self.locked_out(client_id, account, |allowed| allowed.authorize) {
+            return Err(TransactionError::Locked(client_id));
+        }
+        self.get_client(client_id);
+        let Booked {
+            amount,
+            currency,
+            conversion,
+        } = booked;
+
+        self.ledger.transfer(
+            currency,
+            ClientAvailable(client_id, account),
+            ClientHeld(client_id, account),
+            amount,
+        );
+        self.processed_txns.insert(
+            tx,
+            ProcessedTransaction::new_authorization(client_id, amount, currency)
+                .with_account(account)
+                .with_conversion(conversion)
+                .with_recorded_at(self.recorded_at(timestamp)),
+        );
+        if let Some(expiry) = self.authorization_expiry {
+            self.pending_authorizations
+                .push_back((self.clock.rows + expiry, tx));
+        }
+        Ok(())
+    }
+
+    /// Settles a pending authorization, turning it into a withdrawal. Capturing less than was
+    /// authorized releases the remainder back to the client. Capturing more is invalid. If the
+    /// authorization was converted from another currency, so is the captured amount, at the same
+    /// rate.
+    pub fn capture(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+        amount: Option<f64>,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.capture);
+        let authorization = self
+            .processed_txns
+            .get_mut(&tx)
+            .ok_or(TransactionError::UnknownTransaction(tx))?;
+        if authorization.client_id() != client_id {
+            return Err(TransactionError::WrongClient(tx));
+        }
+        if authorization.account() != account {
+            return Err(TransactionError::WrongAccount(tx));
+        }
+        if !authorization.is_pending_authorization() {
+            return Err(TransactionError::NotPendingAuthorization(tx));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+        let authorized = authorization.amount();
+        let captured = match (amount, authorization.conversion()) {
+            (Some(amount), Some(conversion)) => conversion.apply(amount),
+            (Some(amount), None) => amount,
+            (None, _) => authorized,
+        };
+        if captured > authorized || captured < 0. {
+            return Err(TransactionError::InvalidAmount);
+        }
+        authorization.capture(captured);
+        let currency = authorization.currency();
+
+        self.ledger.transfer(
+            currency,
+            ClientHeld(client_id, account),
+            Settlement,
+            captured,
+        );
+        self.ledger.transfer(
+            currency,
+            ClientHeld(client_id, account),
+            ClientAvailable(client_id, account),
+            authorized - captured,
+        );
+        Ok(())
+    }
+
+    /// Releases a pending authorization's funds back to the client.
+    pub fn void(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.void);
+        let authorization = self
+            .processed_txns
+            .get(&tx)
+            .ok_or(TransactionError::UnknownTransaction(tx))?;
+        if authorization.client_id() != client_id {
+            return Err(TransactionError::WrongClient(tx));
+        }
+        if authorization.account() != account {
+            return Err(TransactionError::WrongAccount(tx));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+        if !authorization.is_pending_authorization() {
+            return Err(TransactionError::NotPendingAuthorization(tx));
+        }
+        self.release_authorization(tx);
+        Ok(())
+    }
+
+    /// Refunds part or all of an earlier deposit or withdrawal. Refunding a withdrawal gives the
+    /// client their money back, and refunding a deposit sends it back to where it came from. The
+    /// total refunded can never exceed the original amount. A refund may be given in the currency
+    /// the original was booked in, or in the currency it was converted from, in which case it is
+    /// converted at the original rate.
+    #[allow(clippy::too_many_arguments)]
+    pub fn refund(
+        &mut self,
+        client_id: ClientId,
+        account: AccountId,
+        tx: TxId,
+        amount: f64,
+        currency: Option<Currency>,
+        original_id: TxId,
+        timestamp: Option<Timestamp>,
+    ) -> Result<(), TransactionError> {
+        let locked = self.locked_out(client_id, account, |allowed| allowed.refund);
+        let original = self
+            .processed_txns
+            .get_mut(&original_id)
+            .ok_or(TransactionError::UnknownTransaction(original_id))?;
+        let conversion = original.conversion();
+        let amount = match currency {
+            None => amount,
+            Some(currency) if original.currency() == Some(currency) => amount,
+            Some(currency) => match conversion {
+                Some(conversion) if conversion.from == currency => conversion.apply(amount),
+                _ => return Err(TransactionError::WrongCurrency(original_id)),
+            },
+        };
+        if original.client_id() != client_id {
+            return Err(TransactionError::WrongClient(original_id));
+        }
+        if original.account() != account {
+            return Err(TransactionError::WrongAccount(original_id));
+        }
+        if locked {
+            return Err(TransactionError::Locked(client_id));
+        }
+        if amount < 0. || amount > original.refundable_amount() {
+            return Err(TransactionError::InvalidAmount);
+        }
+        original.add_refunded(amount);
+        let credits_client = !original.credits_client();
+        let currency = original.currency();
+
+        if credits_client {
+            self.ledger.transfer(
+                currency,
+                Settlement,
+                ClientAvailable(client_id, account),
+                amount,
+            );
+        } else {
+            self.ledger.transfer(
+                currency,
+                ClientAvailable(client_id, account),
+                Settlement,
+                amount,
+            );
+        }
+        self.processed_txns.insert(
+            tx,
+            ProcessedTransaction::new_refund(client_id, amount, currency, credits_client)
+                .with_account(account)
+                .with_recorded_at(self.recorded_at(timestamp)),
+        );
+        Ok(())
+    }
+
+    /// Releases any authorizations which have been pending for longer than the configured expiry.
+    fn expire_authorizations(&mut self) {
+        while let Some((expires_at, tx)) = self.pending_authorizations.front().copied() {
+            if expires_at > self.clock.rows {
+                break;
             }
-            if !tx.is_disputed() {
-                // disallow chargebacks on transactions that haven't been disputed
-                return;
+            self.pending_authorizations.pop_front();
+            let Some(authorization) = self
+                .processed_txns
+                .get(&tx)
+                .filter(|processed_txn| processed_txn.is_pending_authorization())
+            else {
+                continue;
+            };
+            let client = authorization.client_id();
+            let account = authorization.account();
+            let currency = self.reported_currency(authorization.currency());
+            let before = self.balance_for_change(client, account, currency);
+            self.release_authorization(tx);
+            if let Some(before) = before {
+                self.record_change(
+                    Cause::AuthorizationExpired(tx),
+                    (client, account, currency),
+                    before,
+                    Ok(()),
+                );
+            }
+        }
+    }
+
+    /// Resolves any disputes which have been open for longer than the configured expiry, as if
+    /// they had been resolved in the client's favour.
+    fn expire_disputes(&mut self) {
+        let Some(expiry) = self.dispute_policy.expiry else {
+            return;
+        };
+        while let Some((tx, opened_at)) = self.pending_disputes.front().copied() {
+            if !self.clock.has_elapsed(opened_at, expiry) {
+                break;
             }
-            let tx_amount = tx.amount();
-            let tx_is_deposit = tx.is_deposit();
-            let client = self.get_client(client);
-            client.locked = true;
-            if tx_is_deposit {
-                client.held -= tx_amount;
-                client.total -= tx_amount;
-            } else {
-                client.total += tx_amount;
+            self.pending_disputes.pop_front();
+            let Some(processed_txn) = self.processed_txns.get(&tx) else {
+                continue;
+            };
+            if !processed_txn.is_disputed()
+                || processed_txn.is_charged_back()
+                || processed_txn.disputed_at() != Some(opened_at)
+            {
+                continue;
+            }
+            let client = processed_txn.client_id();
+            let account = processed_txn.account();
+            let currency = self.reported_currency(processed_txn.currency());
+            let before = self.balance_for_change(client, account, currency);
+            // this only fails if resolutions aren't allowed on the client's locked account
+            if self.resolve(client, account, tx).is_ok() {
+                self.events.push(Event::DisputeExpired {
+                    client,
+                    tx,
+                    at: self.clock,
+                });
+                if let Some(before) = before {
+                    self.record_change(
+                        Cause::DisputeExpired(tx),
+                        (client, account, currency),
+                        before,
+                        Ok(()),
+                    );
+                }
             }
         }
     }
 
-    fn insert_processed_deposit(&mut self, client: ClientId, amount: f64, tx_id: TxId) {
-        self.processed_txns
-            .insert(tx_id, ProcessedTransaction::new_deposit(client, amount));
+    fn release_authorization(&mut self, tx: TxId) {
+        let authorization = self
+            .processed_txns
+            .get_mut(&tx)
+            .expect("callers check that the authorization exists");
+        authorization.release();
+        let client_id = authorization.client_id();
+        let account = authorization.account();
+        let amount = authorization.amount();
+        let currency = authorization.currency();
+        self.ledger.transfer(
+            currency,
+            ClientHeld(client_id, account),
+            ClientAvailable(client_id, account),
+            amount,
+        );
     }
-    fn insert_processed_withdrawal(&mut self, client: ClientId, amount: f64, tx_id: TxId) {
-        self.processed_txns
-            .insert(tx_id, ProcessedTransaction::new_withdrawal(client, amount));
+
+    /// When a row with `timestamp` is being recorded. Rows without a timestamp are taken to have
+    /// happened at the latest time seen.
+    fn recorded_at(&self, timestamp: Option<Timestamp>) -> Clock {
+        Clock {
+            rows: self.clock.rows,
+            time: timestamp.or(self.clock.time),
+        }
     }
 
-    pub fn serialize_to_csv(self) -> Result<String, csv::Error> {
-        let mut wtr = csv::Writer::from_writer(vec![]);
-        wtr.write_record(&["client", "available", "held", "total", "locked"])?;
-        // sort client accounts for testability
-        let mut client_accounts = self
-            .client_accounts
-            .into_iter()
-            .collect::<Vec<(ClientId, ClientAccount)>>();
-        client_accounts.sort_by_key(|(id, _)| *id);
-        for (
-            id,
-            ClientAccount {
-                total,
+    fn insert_processed_deposit(
+        &mut self,
+        client: ClientId,
+        account: AccountId,
+        booked: Booked,
+        tx_id: TxId,
+        timestamp: Option<Timestamp>,
+    ) {
+        self.processed_txns.insert(
+            tx_id,
+            ProcessedTransaction::new_deposit(client, booked.amount, booked.currency)
+                .with_account(account)
+                .with_conversion(booked.conversion)
+                .with_recorded_at(self.recorded_at(timestamp)),
+        );
+    }
+    fn insert_processed_withdrawal(
+        &mut self,
+        client: ClientId,
+        account: AccountId,
+        booked: Booked,
+        tx_id: TxId,
+        timestamp: Option<Timestamp>,
+    ) {
+        self.processed_txns.insert(
+            tx_id,
+            ProcessedTransaction::new_withdrawal(client, booked.amount, booked.currency)
+                .with_account(account)
+                .with_conversion(booked.conversion)
+                .with_recorded_at(self.recorded_at(timestamp)),
+        );
+    }
+
+    /// Whether a transaction with this id has been recorded, and so can be disputed.
+    pub fn has_transaction(&self, tx: TxId) -> bool {
+        self.processed_txns.contains_key(&tx)
+    }
+
+    /// Every account in the ledger and its balance, which can be used to prove that no money was
+    /// created or destroyed while processing.
+    pub fn trial_balance(&self) -> TrialBalance {
+        self.ledger.trial_balance()
+    }
+
+    /// Writes one row per client and currency. The `currency` column is only written if a
+    /// currency other than the default was used, so single-currency input produces the same
+    /// report it always has. Likewise, the `account` column is only written if a client has a
+    /// wallet other than their main account, in which case each wallet gets a row, followed by
+    /// rows for the client as a whole with `all` as the account.
+    pub fn serialize_to_csv(&self) -> Result<String, csv::Error> {
+        self.write_report(false, None)
+    }
+
+    /// Like [State::serialize_to_csv], with an extra `lock_reason` column at the end saying why
+    /// each locked account was locked.
+    pub fn serialize_extended_to_csv(&self) -> Result<String, csv::Error> {
+        self.write_report(true, None)
+    }
+
+    /// Like [State::serialize_to_csv], with only the rows for one client. The columns are the
+    /// same as they would be in the full report.
+    pub fn serialize_client_to_csv(&self, client: ClientId) -> Result<String, csv::Error> {
+        self.write_report(false, Some(client))
+    }
+
+    /// Every row of the account report, sorted by client, wallet, and currency. If any client
+    /// has a wallet other than their main account, each client's wallets are followed by a row
+    /// per currency for the client as a whole.
+    pub fn accounts(&self) -> Vec<AccountReport> {
+        self.report_rows(None).2
+    }
+
+    /// Like [State::accounts], with only the rows for one client.
+    pub fn accounts_of(&self, client: ClientId) -> Vec<AccountReport> {
+        self.report_rows(Some(client)).2
+    }
+
+    /// Where a deposit, withdrawal, authorization, or refund stands, if it was recorded.
+    pub fn transaction_status(&self, tx: TxId) -> Option<TransactionStatus> {
+        let processed_txn = self.processed_txns.get(&tx)?;
+        let dispute = if processed_txn.is_charged_back() {
+            DisputeStatus::ChargedBack
+        } else if processed_txn.is_disputed() {
+            DisputeStatus::Disputed
+        } else {
+            DisputeStatus::Undisputed
+        };
+        Some(TransactionStatus {
+            client: processed_txn.client_id(),
+            account: processed_txn.account(),
+            amount: processed_txn.amount(),
+            currency: processed_txn.currency(),
+            disputable: processed_txn.is_disputable(),
+            dispute,
+        })
+    }
+
+    /// The rows of the report, and whether any client used a currency or a wallet. Those are
+    /// worked out over every client, so that one client's report has the same columns as the full
+    /// report.
+    fn report_rows(&self, only: Option<ClientId>) -> (bool, bool, Vec<AccountReport>) {
+        let with_currency = self
+            .ledger
+            .client_balances()
+            .any(|(_, _, currency)| currency.is_some());
+        let with_wallets = self
+            .ledger
+            .client_balances()
+            .any(|(_, account, _)| account != MAIN_ACCOUNT);
+        let wanted = |id: &ClientId| only.is_none_or(|only| *id == only);
+        // a BTreeSet keeps the rows sorted for testability. Only the wanted client's rows go in,
+        // so one client's report doesn't sort everyone's
+        let mut rows = self
+            .ledger
+            .client_balances()
+            .filter(|(id, _, _)| wanted(id))
+            .collect::<BTreeSet<_>>();
+        // clients whose every transaction was rejected have no balances, but still get a row
+        let clients_with_balances = rows.iter().map(|(id, _, _)| *id).collect::<BTreeSet<_>>();
+        for id in self.client_accounts.keys().filter(|id| wanted(id)) {
+            if !clients_with_balances.contains(id) {
+                rows.insert((*id, MAIN_ACCOUNT, None));
+            }
+        }
+
+        let report =
+            |client, account, currency, (available, held), lock_reason: Option<_>| AccountReport {
+                client,
+                account,
+                currency,
+                available,
                 held,
-                locked,
-            },
-        ) in client_accounts
-        {
-            wtr.write_record(&[
-                id.to_string(),
-                (total - held).to_string(),
-                held.to_string(),
-                total.to_string(),
-                locked.to_string(),
-            ])?;
+                total: available + held,
+                locked: lock_reason.is_some(),
+                lock_reason,
+            };
+        let mut reports = vec![];
+        let mut rows = rows.into_iter().peekable();
+        let mut rollup: BTreeMap<Option<Currency>, (f64, f64)> = BTreeMap::new();
+        while let Some((id, account, currency)) = rows.next() {
+            let available = self.ledger.balance(ClientAvailable(id, account), currency);
+            let held = self.ledger.balance(ClientHeld(id, account), currency);
+            reports.push(report(
+                id,
+                Some(account),
+                currency,
+                (available, held),
+                self.lock_reason(id, account),
+            ));
+            if !with_wallets {
+                continue;
+            }
+            let total = rollup.entry(currency).or_default();
+            total.0 += available;
+            total.1 += held;
+            // rows are sorted by client, so this is the client's last wallet
+            if rows.peek().is_none_or(|(next, _, _)| *next != id) {
+                let lock_reason = self
+                    .client_accounts
+                    .get(&id)
+                    .and_then(|client| client.lock_reason);
+                for (currency, balances) in std::mem::take(&mut rollup) {
+                    reports.push(report(id, None, currency, balances, lock_reason));
+                }
+            }
+        }
+        (with_currency, with_wallets, reports)
+    }
+
+    fn write_report(&self, extended: bool, only: Option<ClientId>) -> Result<String, csv::Error> {
+        let (with_currency, with_wallets, reports) = self.report_rows(only);
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut header = vec!["client", "available", "held", "total", "locked"];
+        if with_currency {
+            header.push("currency");
+        }
+        if with_wallets {
+            header.push("account");
+        }
+        if extended {
+            header.push("lock_reason");
+        }
+        wtr.write_record(header)?;
+
+        for report in reports {
+            let mut record = vec![
+                report.client.to_string(),
+                report.available.to_string(),
+                report.held.to_string(),
+                report.total.to_string(),
+                report.locked.to_string(),
+            ];
+            if with_currency {
+                record.push(report.currency.map(|c| c.to_string()).unwrap_or_default());
+            }
+            if with_wallets {
+                // `None` is the rollup of all of a client's wallets
+                record.push(report.account.map_or("all".to_string(), |a| a.to_string()));
+            }
+            if extended {
+                record.push(
+                    report
+                        .lock_reason
+                        .map(|r| r.to_string())
+                        .unwrap_or_default(),
+                );
+            }
+            wtr.write_record(record)?;
         }
         Ok(String::from_utf8(wtr.into_inner().unwrap()).unwrap())
     }
@@ -0,0 +1,62 @@
+use super::{Clock, TransactionError};
+use crate::{AccountId, ClientId, Currency, Transaction, TxId};
+
+/// What one row of input did to the wallet it was for, or why it did nothing, or a movement the
+/// state made on a wallet on its own. Only rows for a client produce a change of their own.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub cause: Cause,
+    pub client: ClientId,
+    pub account: AccountId,
+    /// The change's place on the clock. For a row, after it was applied.
+    pub at: Clock,
+    /// The currency the balances are in: the one the row was booked in, or for rows which refer
+    /// to an earlier transaction, that transaction's.
+    pub currency: Option<Currency>,
+    /// Always `Ok` for movements the state made on its own.
+    pub result: Result<(), TransactionError>,
+    pub before: Balance,
+    pub after: Balance,
+}
+
+/// What made a [Change].
+#[derive(Debug, Clone)]
+pub enum Cause {
+    /// A row for the wallet.
+    Row(Transaction),
+    /// Interest paid or charged, and any fee taken, for one balance when accruing.
+    Accrual,
+    /// A dispute of this transaction was open for too long, and was resolved.
+    DisputeExpired(TxId),
+    /// This authorization was pending for too long, and its funds were released.
+    AuthorizationExpired(TxId),
+}
+
+impl Cause {
+    /// A short name for the cause: the row's type, or what the state did.
+    pub fn kind(&self) -> &str {
+        match self {
+            Cause::Row(transaction) => transaction.kind(),
+            Cause::Accrual => "accrual",
+            Cause::DisputeExpired(_) => "dispute_expired",
+            Cause::AuthorizationExpired(_) => "authorization_expired",
+        }
+    }
+
+    /// The transaction the change was for, if any.
+    pub fn tx(&self) -> Option<TxId> {
+        match self {
+            Cause::Row(transaction) => transaction.tx(),
+            Cause::Accrual => None,
+            Cause::DisputeExpired(tx) | Cause::AuthorizationExpired(tx) => Some(*tx),
+        }
+    }
+}
+
+/// A wallet's balances in one currency.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Balance {
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
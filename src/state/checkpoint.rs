@@ -0,0 +1,109 @@
+use super::{Clock, State};
+use crate::{Timestamp, Transaction};
+
+/// A point in the input to report balances at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// Just after this many rows had been processed.
+    Row(u64),
+    /// Just after the last row processed while the clock was at or before this time. Rows before
+    /// the first timestamped row count as being before any time.
+    Time(Timestamp),
+}
+
+/// Copies of a [State] taken every so many rows, along with every row processed since the first
+/// copy. Any earlier state can then be rebuilt by replaying the rows after the closest copy, which
+/// never means replaying more than `every` rows.
+///
+/// Every copy is a whole [State], so keeping them all grows with both the length of the input and
+/// the number of copies. With a target, only the latest copy taken before the target and the rows
+/// after it are kept, so at most one copy and `every` rows are held at a time.
+#[derive(Debug, Clone)]
+pub(super) struct Checkpoints {
+    every: u64,
+    /// the only point that will be rebuilt, if it is known up front
+    target: Option<AsOf>,
+    /// copies of the state, and how many rows the clock had counted when each was taken, which is
+    /// a multiple of `every`
+    snapshots: Vec<(u64, State)>,
+    /// every row processed since the first copy kept, and the clock just after it
+    journal: Vec<(Clock, Transaction)>,
+    /// how many rows the clock had counted when the journal starts
+    journal_start: u64,
+    /// whether the target has been passed, so nothing more needs to be kept
+    passed: bool,
+}
+
+impl Checkpoints {
+    pub fn new(every: u64, target: Option<AsOf>) -> Self {
+        Checkpoints {
+            every: every.max(1),
+            target,
+            snapshots: vec![],
+            journal: vec![],
+            journal_start: 0,
+            passed: false,
+        }
+    }
+
+    /// Whether a copy of the state should be taken before a row is processed at `clock`.
+    pub fn is_due(&self, clock: Clock) -> bool {
+        !self.passed
+            && clock.rows.is_multiple_of(self.every)
+            && self
+                .snapshots
+                .last()
+                .is_none_or(|(taken, _)| *taken < clock.rows)
+    }
+
+    pub fn push_snapshot(&mut self, clock: Clock, state: State) {
+        if self.target.is_some() {
+            // the target hasn't been passed yet, so it is at or after this copy
+            self.snapshots.clear();
+            self.journal.clear();
+            self.journal_start = clock.rows;
+        }
+        self.snapshots.push((clock.rows, state));
+    }
+
+    pub fn record(&mut self, clock: Clock, transaction: Transaction) {
+        if self.passed {
+            return;
+        }
+        self.journal.push((clock, transaction));
+        self.passed = match self.target {
+            Some(AsOf::Row(rows)) => clock.rows >= rows,
+            Some(AsOf::Time(time)) => clock.time.is_some_and(|now| now > time),
+            None => false,
+        };
+    }
+
+    /// Rebuilds the state as it was at `as_of`, or returns `None` if no rows have been processed.
+    /// With a target, only the target can be rebuilt.
+    pub fn rebuild(&self, as_of: AsOf) -> Option<State> {
+        let end = self.journal_start + self.journal.len() as u64;
+        let rows = match as_of {
+            AsOf::Row(rows) => rows,
+            AsOf::Time(time) => {
+                self.journal_start
+                    + self
+                        .journal
+                        .partition_point(|(clock, _)| clock.time.is_none_or(|now| now <= time))
+                        as u64
+            }
+        };
+        let rows = rows.clamp(self.journal_start, end);
+        // a copy is only taken before the row after it, so there may not be one at `rows` yet
+        let index = self
+            .snapshots
+            .partition_point(|(taken, _)| *taken <= rows)
+            .checked_sub(1)?;
+        let (taken, snapshot) = &self.snapshots[index];
+        let mut state = snapshot.clone();
+        let replay = (taken - self.journal_start) as usize..(rows - self.journal_start) as usize;
+        for (_, transaction) in &self.journal[replay] {
+            state.transact(transaction.clone());
+        }
+        Some(state)
+    }
+}
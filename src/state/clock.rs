@@ -0,0 +1,114 @@
+use crate::Timestamp;
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A span of time on the [Clock]. If both ends of the span are timestamped, `millis` is used.
+/// Otherwise, the input has no notion of time, so the number of rows in between is compared to
+/// `rows` instead. A limit that isn't set never elapses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Window {
+    pub millis: Option<u64>,
+    pub rows: Option<u64>,
+}
+
+impl Window {
+    /// A window of whole days, which never elapses between rows without timestamps.
+    pub fn days(days: u64) -> Self {
+        Window {
+            millis: Some(days.saturating_mul(MILLIS_PER_DAY)),
+            rows: None,
+        }
+    }
+
+    pub fn rows(rows: u64) -> Self {
+        Window {
+            millis: None,
+            rows: Some(rows),
+        }
+    }
+}
+
+/// The logical clock of a [crate::State]. It advances with every row of input: `rows` counts
+/// them, and `time` is the latest timestamp seen so far. Input without timestamps only advances
+/// `rows`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Clock {
+    pub rows: u64,
+    pub time: Option<Timestamp>,
+}
+
+impl Clock {
+    /// Advances the clock for a new row. Rows timestamped more than `tolerance` milliseconds
+    /// before the latest timestamp are rejected, and `false` is returned. Rows which are late, but
+    /// within the tolerance, are accepted without turning the clock back.
+    pub fn tick(&mut self, timestamp: Option<Timestamp>, tolerance: u64) -> bool {
+        self.rows += 1;
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp,
+            None => return true,
+        };
+        match self.time {
+            Some(now) if timestamp < now => {
+                now.as_millis().saturating_sub(timestamp.as_millis()) <= tolerance as i64
+            }
+            _ => {
+                self.time = Some(timestamp);
+                true
+            }
+        }
+    }
+}
+
+impl Clock {
+    /// Whether more than `window` has passed between `earlier` and this clock.
+    pub fn has_elapsed(&self, earlier: Clock, window: Window) -> bool {
+        match (earlier.time, self.time, window.millis) {
+            (Some(then), Some(now), Some(millis)) => {
+                // windows too long to fit are longer than any span between two timestamps
+                now.as_millis().saturating_sub(then.as_millis())
+                    > i64::try_from(millis).unwrap_or(i64::MAX)
+            }
+            _ => window
+                .rows
+                .is_some_and(|rows| self.rows.saturating_sub(earlier.rows) > rows),
+        }
+    }
+}
+
+#[test]
+fn test_tick() {
+    let at = |millis| Some(Timestamp::from_millis(millis));
+    let mut clock = Clock::default();
+    assert!(clock.tick(at(1_000), 100));
+    assert!(clock.tick(None, 100));
+    assert!(clock.tick(at(900), 100));
+    assert!(!clock.tick(at(899), 100));
+    assert!(clock.tick(at(2_000), 100));
+    assert_eq!(
+        clock,
+        Clock {
+            rows: 5,
+            time: at(2_000)
+        }
+    );
+}
+
+#[test]
+fn test_has_elapsed() {
+    let at = |rows, millis: Option<i64>| Clock {
+        rows,
+        time: millis.map(Timestamp::from_millis),
+    };
+    let window = Window {
+        millis: Some(1_000),
+        rows: Some(10),
+    };
+    assert!(!at(20, Some(1_000)).has_elapsed(at(1, Some(0)), window));
+    assert!(at(2, Some(1_001)).has_elapsed(at(1, Some(0)), window));
+    assert!(!at(11, None).has_elapsed(at(1, Some(0)), window));
+    assert!(at(12, None).has_elapsed(at(1, Some(0)), window));
+    assert!(!at(100, None).has_elapsed(at(1, None), Window::days(1)));
+    // extreme timestamps and windows don't overflow
+    assert!(at(2, Some(i64::MAX)).has_elapsed(at(1, Some(i64::MIN)), window));
+    assert!(!at(2, Some(i64::MAX)).has_elapsed(at(1, Some(i64::MIN)), Window::days(u64::MAX)));
+}
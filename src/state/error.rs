@@ -0,0 +1,44 @@
+use crate::{ClientId, Currency, TxId};
+
+/// Why a transaction was not applied.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum TransactionError {
+    #[error("the row's timestamp is too far behind the clock")]
+    Late,
+    #[error("there is no rate to convert {0}")]
+    NoRate(Currency),
+    #[error("client {0} is locked")]
+    Locked(ClientId),
+    #[error("client {client} is blocked: {reason}")]
+    Blocked { client: ClientId, reason: String },
+    #[error("transaction {0} does not exist")]
+    UnknownTransaction(TxId),
+    #[error("transaction {0} belongs to another client")]
+    WrongClient(TxId),
+    #[error("transaction {0} belongs to another of the client's accounts")]
+    WrongAccount(TxId),
+    #[error("transaction {0} already exists")]
+    DuplicateTransaction(TxId),
+    #[error("transaction {0} can't be disputed")]
+    NotDisputable(TxId),
+    #[error("transaction {0} has been refunded in full")]
+    Refunded(TxId),
+    #[error("transaction {0} is no longer within the dispute filing window")]
+    FilingWindowClosed(TxId),
+    #[error("transaction {0} was in another currency")]
+    WrongCurrency(TxId),
+    #[error("transaction {0} is already disputed")]
+    AlreadyDisputed(TxId),
+    #[error("transaction {0} has already been charged back")]
+    ChargedBack(TxId),
+    #[error("transaction {0} is not disputed")]
+    NotDisputed(TxId),
+    #[error("transaction {0} is not a pending authorization")]
+    NotPendingAuthorization(TxId),
+    #[error("the amount is out of range")]
+    InvalidAmount,
+    #[error("rejected by the {0} rule")]
+    Rule(&'static str),
+    #[error("unrecognized transaction type {0:?}")]
+    Unrecognized(String),
+}
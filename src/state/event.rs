@@ -0,0 +1,21 @@
+use super::Clock;
+use crate::{rules::Outcome, ClientId, TxId};
+
+/// Something the state did on its own, rather than in direct response to a row of input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A dispute was open for too long, and was resolved in the client's favour.
+    DisputeExpired {
+        client: ClientId,
+        tx: TxId,
+        at: Clock,
+    },
+    /// A row broke one of the rules. The outcome says what was done about it.
+    RuleBroken {
+        client: ClientId,
+        tx: Option<TxId>,
+        rule: &'static str,
+        outcome: Outcome,
+        at: Clock,
+    },
+}
@@ -0,0 +1,203 @@
+use crate::{AccountId, ClientId, Currency, MAIN_ACCOUNT};
+use fnv::FnvHashMap;
+use std::fmt;
+
+/// Any trial balance whose net is further from zero than this is considered out of balance. Every
+/// transfer moves the exact same `f64` out of one account and into another, but summing many
+/// accounts in an arbitrary order can still leave a little floating point dust behind.
+const BALANCE_TOLERANCE: f64 = 1e-6;
+
+/// An account in the double-entry ledger. Client balances are liabilities of the system, and the
+/// remaining accounts represent where that money came from or went to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum LedgerAccount {
+    /// Funds the client is free to withdraw from one of their wallets.
+    ClientAvailable(ClientId, AccountId),
+    /// Funds frozen in one of the client's wallets while a transaction is disputed.
+    ClientHeld(ClientId, AccountId),
+    /// The outside world: banks, card networks, and whatever else funds enter and leave through.
+    Settlement,
+    /// Fees collected from clients.
+    Fees,
+    /// Money the system had to give back to a client after a chargeback on a withdrawal.
+    ChargebackLoss,
+    /// Interest paid to clients on positive balances.
+    InterestExpense,
+    /// Interest charged to clients on overdrawn balances.
+    InterestIncome,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use LedgerAccount::*;
+        match self {
+            // the main account is written without its id, as it was before clients had wallets
+            ClientAvailable(id, MAIN_ACCOUNT) => write!(f, "client_available:{}", id),
+            ClientHeld(id, MAIN_ACCOUNT) => write!(f, "client_held:{}", id),
+            ClientAvailable(id, account) => write!(f, "client_available:{}/{}", id, account),
+            ClientHeld(id, account) => write!(f, "client_held:{}/{}", id, account),
+            Settlement => f.write_str("settlement"),
+            Fees => f.write_str("fees"),
+            ChargebackLoss => f.write_str("chargeback_loss"),
+            InterestExpense => f.write_str("interest_expense"),
+            InterestIncome => f.write_str("interest_income"),
+        }
+    }
+}
+
+/// The double-entry ledger underneath every client balance. Balances are never written directly;
+/// the only way to change one is a [Ledger::transfer], which posts a debit and a matching credit.
+/// That way, the books always net to zero.
+///
+/// Each currency is effectively its own ledger. Money never moves between currencies, so every
+/// currency nets to zero on its own. `None` is the default, unnamed currency.
+#[derive(Default, Debug, Clone)]
+pub struct Ledger {
+    balances: FnvHashMap<(LedgerAccount, Option<Currency>), f64>,
+}
+
+impl Ledger {
+    /// Moves `amount` of `currency` out of `from` and into `to`.
+    pub fn transfer(
+        &mut self,
+        currency: Option<Currency>,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: f64,
+    ) {
+        *self.balances.entry((from, currency)).or_default() -= amount;
+        *self.balances.entry((to, currency)).or_default() += amount;
+    }
+
+    /// The current balance of `account` in `currency`. Accounts that have never been posted to
+    /// have a balance of zero.
+    pub fn balance(&self, account: LedgerAccount, currency: Option<Currency>) -> f64 {
+        self.balances
+            .get(&(account, currency))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Every wallet and currency each client has a balance in. A wallet can appear more than
+    /// once.
+    pub fn client_balances(
+        &self,
+    ) -> impl Iterator<Item = (ClientId, AccountId, Option<Currency>)> + '_ {
+        self.balances
+            .keys()
+            .filter_map(|(account, currency)| match account {
+                LedgerAccount::ClientAvailable(id, wallet)
+                | LedgerAccount::ClientHeld(id, wallet) => Some((*id, *wallet, *currency)),
+                _ => None,
+            })
+    }
+
+    pub fn trial_balance(&self) -> TrialBalance {
+        let mut rows = self
+            .balances
+            .iter()
+            .map(|((account, currency), balance)| (*account, *currency, *balance))
+            .collect::<Vec<_>>();
+        // sort accounts for testability
+        rows.sort_by_key(|(account, currency, _)| (*currency, *account));
+        TrialBalance { rows }
+    }
+}
+
+/// Every account in the ledger alongside its balance. If nothing has gone wrong, the balances in
+/// each currency net to zero.
+#[derive(Debug, Clone)]
+pub struct TrialBalance {
+    rows: Vec<(LedgerAccount, Option<Currency>, f64)>,
+}
+
+impl TrialBalance {
+    pub fn rows(&self) -> &[(LedgerAccount, Option<Currency>, f64)] {
+        &self.rows
+    }
+
+    /// The sum of every account balance in each currency.
+    pub fn net(&self) -> Vec<(Option<Currency>, f64)> {
+        let mut net: Vec<(Option<Currency>, f64)> = vec![];
+        // rows are sorted by currency, so each currency's rows are contiguous
+        for (_, currency, balance) in &self.rows {
+            match net.last_mut() {
+                Some((last, sum)) if last == currency => *sum += balance,
+                _ => net.push((*currency, *balance)),
+            }
+        }
+        net
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.net()
+            .iter()
+            .all(|(_, net)| net.abs() < BALANCE_TOLERANCE)
+    }
+
+    /// Writes the trial balance as CSV. The `currency` column is only written if a currency other
+    /// than the default was used.
+    pub fn serialize_to_csv(&self) -> Result<String, csv::Error> {
+        let with_currency = self.rows.iter().any(|(_, currency, _)| currency.is_some());
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut write_row = |account: String, currency: String, balance: String| {
+            if with_currency {
+                wtr.write_record([account, currency, balance])
+            } else {
+                wtr.write_record([account, balance])
+            }
+        };
+        let currency_name = |currency: Option<Currency>| match currency {
+            Some(currency) => currency.to_string(),
+            None => String::new(),
+        };
+        write_row("account".into(), "currency".into(), "balance".into())?;
+        for (account, currency, balance) in &self.rows {
+            write_row(
+                account.to_string(),
+                currency_name(*currency),
+                balance.to_string(),
+            )?;
+        }
+        for (currency, net) in self.net() {
+            write_row("net".into(), currency_name(currency), net.to_string())?;
+        }
+        Ok(String::from_utf8(wtr.into_inner().unwrap()).unwrap())
+    }
+}
+
+#[test]
+fn test_transfers_balance() {
+    use LedgerAccount::*;
+    let mut ledger = Ledger::default();
+    let eur = Some("EUR".parse().unwrap());
+    ledger.transfer(None, Settlement, ClientAvailable(1, 0), 10.0);
+    ledger.transfer(None, ClientAvailable(1, 0), ClientHeld(1, 0), 4.0);
+    ledger.transfer(None, ChargebackLoss, ClientAvailable(2, 0), 0.5);
+    ledger.transfer(eur, Settlement, ClientAvailable(1, 0), 3.0);
+    ledger.transfer(eur, ClientAvailable(1, 0), ClientAvailable(1, 2), 1.0);
+
+    assert_eq!(ledger.balance(ClientAvailable(1, 0), None), 6.0);
+    assert_eq!(ledger.balance(ClientAvailable(1, 0), eur), 2.0);
+    assert_eq!(ledger.balance(ClientAvailable(1, 2), eur), 1.0);
+    assert_eq!(ledger.balance(ClientHeld(1, 0), None), 4.0);
+    assert_eq!(ledger.balance(Fees, None), 0.0);
+
+    let trial_balance = ledger.trial_balance();
+    assert!(trial_balance.is_balanced());
+    assert_eq!(
+        trial_balance.serialize_to_csv().unwrap(),
+        "account,currency,balance
+client_available:1,,6
+client_available:2,,0.5
+client_held:1,,4
+settlement,,-10
+chargeback_loss,,-0.5
+client_available:1,EUR,2
+client_available:1/2,EUR,1
+settlement,EUR,-3
+net,,0
+net,EUR,0
+"
+    );
+}
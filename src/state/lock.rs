@@ -0,0 +1,180 @@
+use crate::TxId;
+use std::{fmt, str::FromStr};
+
+/// When a chargeback locks the client's account.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LockPolicy {
+    /// Every chargeback locks the account.
+    #[default]
+    AnyChargeback,
+    /// Only chargebacks of transactions that credited the client, like deposits, lock the account.
+    /// Chargebacks of withdrawals give the client money back, so they are no reason to lock.
+    CreditChargebacks,
+    /// The account is locked on the client's `n`th chargeback.
+    AfterChargebacks(u64),
+    /// The account is locked once the client's chargebacks exceed this fraction of their deposits.
+    ChargebackRatio(f64),
+}
+
+impl LockPolicy {
+    /// Whether a chargeback should lock the account, given the client's history including it.
+    pub(super) fn should_lock(
+        &self,
+        credited_client: bool,
+        chargebacks: u64,
+        deposits: u64,
+    ) -> bool {
+        match self {
+            LockPolicy::AnyChargeback => true,
+            LockPolicy::CreditChargebacks => credited_client,
+            LockPolicy::AfterChargebacks(n) => chargebacks >= *n,
+            LockPolicy::ChargebackRatio(ratio) => chargebacks as f64 > ratio * deposits as f64,
+        }
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = ();
+    /// Parses `any`, `credits`, `after:<n>`, or `ratio:<fraction>`.
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().split_once(':') {
+            None if raw.trim() == "any" => Ok(LockPolicy::AnyChargeback),
+            None if raw.trim() == "credits" => Ok(LockPolicy::CreditChargebacks),
+            Some(("after", n)) => n.parse().map(LockPolicy::AfterChargebacks).map_err(|_| ()),
+            Some(("ratio", ratio)) => ratio
+                .parse()
+                .map(LockPolicy::ChargebackRatio)
+                .map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which transactions a client may still make once their account is locked. By default, a locked
+/// client can't move money in or out, but disputes against their earlier transactions can still
+/// be settled, and pending authorizations can be voided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockedAccountPolicy {
+    pub deposit: bool,
+    pub withdrawal: bool,
+    pub dispute: bool,
+    pub resolve: bool,
+    pub chargeback: bool,
+    pub authorize: bool,
+    pub capture: bool,
+    pub void: bool,
+    pub refund: bool,
+}
+
+impl LockedAccountPolicy {
+    /// A policy which allows nothing on locked accounts.
+    pub fn deny_all() -> Self {
+        LockedAccountPolicy {
+            deposit: false,
+            withdrawal: false,
+            dispute: false,
+            resolve: false,
+            chargeback: false,
+            authorize: false,
+            capture: false,
+            void: false,
+            refund: false,
+        }
+    }
+}
+
+impl Default for LockedAccountPolicy {
+    fn default() -> Self {
+        LockedAccountPolicy {
+            dispute: true,
+            resolve: true,
+            chargeback: true,
+            void: true,
+            ..LockedAccountPolicy::deny_all()
+        }
+    }
+}
+
+impl FromStr for LockedAccountPolicy {
+    type Err = ();
+    /// Parses a comma separated list of the transaction types to allow, like `dispute,resolve`.
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let mut policy = LockedAccountPolicy::deny_all();
+        for r#type in raw.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let allowed = match r#type.to_ascii_lowercase().as_str() {
+                "deposit" => &mut policy.deposit,
+                "withdrawal" => &mut policy.withdrawal,
+                "dispute" => &mut policy.dispute,
+                "resolve" => &mut policy.resolve,
+                "chargeback" => &mut policy.chargeback,
+                "authorize" => &mut policy.authorize,
+                "capture" => &mut policy.capture,
+                "void" => &mut policy.void,
+                "refund" => &mut policy.refund,
+                _ => return Err(()),
+            };
+            *allowed = true;
+        }
+        Ok(policy)
+    }
+}
+
+/// What a lock applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockScope {
+    /// Every one of the client's wallets is locked.
+    #[default]
+    Client,
+    /// Only the wallet the locking transaction was in is locked.
+    Wallet,
+}
+
+impl FromStr for LockScope {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim() {
+            "client" => Ok(LockScope::Client),
+            "wallet" => Ok(LockScope::Wallet),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Why an account was locked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// A chargeback of this transaction locked the account under the [LockPolicy].
+    Chargeback(TxId),
+    /// The client broke a rule whose outcome is to lock.
+    Rule(&'static str),
+}
+
+impl fmt::Display for LockReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockReason::Chargeback(tx) => write!(f, "chargeback:{}", tx),
+            LockReason::Rule(rule) => write!(f, "rule:{}", rule),
+        }
+    }
+}
+
+#[test]
+fn test_lock_policy() {
+    assert_eq!("any".parse(), Ok(LockPolicy::AnyChargeback));
+    assert_eq!("credits".parse(), Ok(LockPolicy::CreditChargebacks));
+    assert_eq!("after:3".parse(), Ok(LockPolicy::AfterChargebacks(3)));
+    assert_eq!("ratio:0.25".parse(), Ok(LockPolicy::ChargebackRatio(0.25)));
+    assert_eq!("after".parse::<LockPolicy>(), Err(()));
+    assert_eq!(
+        "dispute, resolve,chargeback,void".parse(),
+        Ok(LockedAccountPolicy::default())
+    );
+    assert_eq!("".parse(), Ok(LockedAccountPolicy::deny_all()));
+    assert_eq!("transfer".parse::<LockedAccountPolicy>(), Err(()));
+
+    assert!(!LockPolicy::CreditChargebacks.should_lock(false, 1, 1));
+    assert!(!LockPolicy::AfterChargebacks(2).should_lock(true, 1, 0));
+    assert!(LockPolicy::AfterChargebacks(2).should_lock(true, 2, 0));
+    assert!(!LockPolicy::ChargebackRatio(0.5).should_lock(true, 1, 2));
+    assert!(LockPolicy::ChargebackRatio(0.5).should_lock(true, 2, 3));
+}
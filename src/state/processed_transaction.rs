@@ -1,6 +1,7 @@
-use crate::ClientId;
-/// A processed transaction is a processed deposit or withdrawal. These deserve a different data
-/// representation from Transactions for the following reasons:
+use super::Clock;
+use crate::{fx::Conversion, AccountId, ClientId, Currency, MAIN_ACCOUNT};
+/// A processed transaction is a processed deposit, withdrawal, authorization, or refund. These
+/// deserve a different data representation from Transactions for the following reasons:
 /// 1. [Transaction]s are limited in their representation due to the fact that they are a direct
 ///    model of the incoming user data. If we were to alter that data type, we would be directly
 ///    impacting our representation of user input. Having two types, one for the internal
@@ -11,40 +12,182 @@ use crate::ClientId;
 ///    layout that does not accurately represent the data, i.e. an enum with five variants but only
 ///    two of which are ever constructed.
 /// 3. Processed transactions can be disputed and therefore need another flag for that.
+///
+/// Authorizations are kept here too, since a captured authorization becomes a withdrawal and must
+/// be disputable like any other.
 #[derive(Debug, Clone)]
 pub struct ProcessedTransaction {
     r#type: ProcessedTransactionType,
     client: ClientId,
+    /// the client's wallet the transaction was booked to
+    account: AccountId,
     amount: f64,
+    /// the currency `amount` was booked in
+    currency: Option<Currency>,
+    /// if this transaction arrived in a foreign currency, how it was converted into `currency`
+    conversion: Option<Conversion>,
+    /// the row this transaction arrived on, and when it happened
+    recorded_at: Clock,
     disputed: bool,
+    /// when the current dispute was filed
+    disputed_at: Option<Clock>,
+    /// whether a chargeback has already reverted this transaction
+    charged_back: bool,
+    /// how much of this transaction has been refunded so far
+    refunded: f64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 enum ProcessedTransactionType {
     Deposit,
     Withdrawal,
+    /// an authorization whose funds are still held, waiting to be captured or voided
+    Authorization,
+    /// an authorization that was voided or expired before it was captured
+    ReleasedAuthorization,
+    /// a refund of an earlier transaction. Refunding a withdrawal credits the client, and
+    /// refunding a deposit debits them.
+    Refund {
+        credits_client: bool,
+    },
 }
 
 impl ProcessedTransaction {
-    pub fn new_deposit(client: ClientId, amount: f64) -> Self {
+    pub fn new_deposit(client: ClientId, amount: f64, currency: Option<Currency>) -> Self {
         ProcessedTransaction {
             r#type: ProcessedTransactionType::Deposit,
             amount,
+            currency,
+            conversion: None,
+            recorded_at: Clock::default(),
             client,
+            account: MAIN_ACCOUNT,
             disputed: false,
+            disputed_at: None,
+            charged_back: false,
+            refunded: 0.,
         }
     }
-    pub fn new_withdrawal(client: ClientId, amount: f64) -> Self {
+    pub fn new_withdrawal(client: ClientId, amount: f64, currency: Option<Currency>) -> Self {
         ProcessedTransaction {
             r#type: ProcessedTransactionType::Withdrawal,
             amount,
+            currency,
+            conversion: None,
+            recorded_at: Clock::default(),
             client,
+            account: MAIN_ACCOUNT,
             disputed: false,
+            disputed_at: None,
+            charged_back: false,
+            refunded: 0.,
         }
     }
 
-    pub fn is_deposit(&self) -> bool {
-        self.r#type == ProcessedTransactionType::Deposit
+    pub fn new_authorization(client: ClientId, amount: f64, currency: Option<Currency>) -> Self {
+        ProcessedTransaction {
+            r#type: ProcessedTransactionType::Authorization,
+            amount,
+            currency,
+            conversion: None,
+            recorded_at: Clock::default(),
+            client,
+            account: MAIN_ACCOUNT,
+            disputed: false,
+            disputed_at: None,
+            charged_back: false,
+            refunded: 0.,
+        }
+    }
+
+    pub fn new_refund(
+        client: ClientId,
+        amount: f64,
+        currency: Option<Currency>,
+        credits_client: bool,
+    ) -> Self {
+        ProcessedTransaction {
+            r#type: ProcessedTransactionType::Refund { credits_client },
+            amount,
+            currency,
+            conversion: None,
+            recorded_at: Clock::default(),
+            client,
+            account: MAIN_ACCOUNT,
+            disputed: false,
+            disputed_at: None,
+            charged_back: false,
+            refunded: 0.,
+        }
+    }
+
+    /// Whether this transaction added funds to the client's account. Disputing one of these
+    /// holds the funds, since the client may not have been entitled to them.
+    pub fn credits_client(&self) -> bool {
+        matches!(
+            self.r#type,
+            ProcessedTransactionType::Deposit
+                | ProcessedTransactionType::Refund {
+                    credits_client: true
+                }
+        )
+    }
+
+    /// Whether this is an authorization that has not yet been captured or released.
+    pub fn is_pending_authorization(&self) -> bool {
+        self.r#type == ProcessedTransactionType::Authorization
+    }
+
+    /// Only settled funds can be disputed, so pending and released authorizations cannot be.
+    pub fn is_disputable(&self) -> bool {
+        matches!(
+            self.r#type,
+            ProcessedTransactionType::Deposit
+                | ProcessedTransactionType::Withdrawal
+                | ProcessedTransactionType::Refund { .. }
+        )
+    }
+
+    /// How much of this transaction can still be refunded. Refunds themselves, authorizations, and
+    /// transactions under dispute can't be refunded at all.
+    pub fn refundable_amount(&self) -> f64 {
+        match self.r#type {
+            ProcessedTransactionType::Deposit | ProcessedTransactionType::Withdrawal
+                if !self.disputed =>
+            {
+                self.amount - self.refunded
+            }
+            _ => 0.,
+        }
+    }
+
+    /// How much a dispute holds and a chargeback reverts: whatever hasn't already been refunded,
+    /// since that has already gone back.
+    pub fn disputable_amount(&self) -> f64 {
+        self.amount - self.refunded
+    }
+
+    /// Whether refunds have given back all of this transaction. Transactions for nothing were
+    /// never refunded, so still count as whole.
+    pub fn is_refunded_in_full(&self) -> bool {
+        self.refunded > 0. && self.refunded >= self.amount
+    }
+
+    pub fn add_refunded(&mut self, amount: f64) {
+        self.refunded += amount;
+    }
+
+    /// Turns a pending authorization into a withdrawal of the captured amount.
+    pub fn capture(&mut self, amount: f64) {
+        debug_assert!(self.is_pending_authorization());
+        self.r#type = ProcessedTransactionType::Withdrawal;
+        self.amount = amount;
+    }
+
+    /// Marks a pending authorization as voided or expired.
+    pub fn release(&mut self) {
+        debug_assert!(self.is_pending_authorization());
+        self.r#type = ProcessedTransactionType::ReleasedAuthorization;
     }
 
     pub fn set_disputed(&mut self, val: bool) {
@@ -55,10 +198,59 @@ impl ProcessedTransaction {
         self.client
     }
 
+    pub fn with_account(mut self, account: AccountId) -> Self {
+        self.account = account;
+        self
+    }
+
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
     pub fn amount(&self) -> f64 {
         self.amount
     }
 
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn with_conversion(mut self, conversion: Option<Conversion>) -> Self {
+        self.conversion = conversion;
+        self
+    }
+
+    pub fn conversion(&self) -> Option<Conversion> {
+        self.conversion
+    }
+
+    pub fn with_recorded_at(mut self, recorded_at: Clock) -> Self {
+        self.recorded_at = recorded_at;
+        self
+    }
+
+    pub fn recorded_at(&self) -> Clock {
+        self.recorded_at
+    }
+
+    pub fn set_disputed_at(&mut self, at: Clock) {
+        self.disputed_at = Some(at);
+    }
+
+    pub fn disputed_at(&self) -> Option<Clock> {
+        self.disputed_at
+    }
+
+    /// Ends the dispute for good; a charged back transaction can't be disputed again.
+    pub fn set_charged_back(&mut self) {
+        self.charged_back = true;
+        self.disputed = false;
+    }
+
+    pub fn is_charged_back(&self) -> bool {
+        self.charged_back
+    }
+
     pub fn is_disputed(&self) -> bool {
         self.disputed
     }
@@ -0,0 +1,48 @@
+use super::LockReason;
+use crate::{AccountId, ClientId, Currency};
+use std::fmt;
+
+/// One row of the account report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccountReport {
+    pub client: ClientId,
+    /// `None` for the row which adds up all of the client's wallets.
+    pub account: Option<AccountId>,
+    pub currency: Option<Currency>,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+    pub lock_reason: Option<LockReason>,
+}
+
+/// Where a recorded deposit, withdrawal, authorization, or refund stands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionStatus {
+    pub client: ClientId,
+    pub account: AccountId,
+    /// The amount as it was booked, after any conversion.
+    pub amount: f64,
+    pub currency: Option<Currency>,
+    /// Whether the transaction is one that can be disputed at all.
+    pub disputable: bool,
+    pub dispute: DisputeStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStatus {
+    /// Not under dispute, either because it never was or because the dispute was resolved.
+    Undisputed,
+    Disputed,
+    ChargedBack,
+}
+
+impl fmt::Display for DisputeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisputeStatus::Undisputed => "undisputed",
+            DisputeStatus::Disputed => "disputed",
+            DisputeStatus::ChargedBack => "charged_back",
+        })
+    }
+}
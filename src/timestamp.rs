@@ -0,0 +1,136 @@
+use crate::Date;
+use std::{fmt, str::FromStr};
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// A point in time, in milliseconds since the Unix epoch. Timestamps can be read from either
+/// epoch milliseconds (`1706659200000`) or RFC 3339 (`2024-01-31T00:00:00Z`), and are always
+/// written as RFC 3339 in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    pub fn from_millis(millis: i64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub fn as_millis(&self) -> i64 {
+        self.0
+    }
+
+    /// The UTC calendar date this timestamp falls on.
+    pub fn date(&self) -> Date {
+        Date::from_days_since_epoch(self.0.div_euclid(MILLIS_PER_DAY))
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let raw = raw.trim();
+        if let Ok(millis) = raw.parse::<i64>() {
+            return Ok(Timestamp(millis));
+        }
+        parse_rfc3339(raw).ok_or(())
+    }
+}
+
+/// Parses `YYYY-MM-DDTHH:MM:SS`, with optional fractional seconds, followed by `Z` or a `+HH:MM`
+/// or `-HH:MM` offset. Fractions finer than a millisecond are truncated.
+fn parse_rfc3339(raw: &str) -> Option<Timestamp> {
+    if !raw.is_ascii() || raw.len() < 20 {
+        return None;
+    }
+    let (date, rest) = raw.split_at(10);
+    let date: Date = date.parse().ok()?;
+    let rest = rest.strip_prefix(['T', 't', ' '])?;
+
+    let number = |raw: &str| -> Option<i64> {
+        raw.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| raw.parse().ok())
+            .flatten()
+    };
+    let (time, mut rest) = rest.split_at(8);
+    let mut parts = time.split(':');
+    let hours = number(parts.next()?)?;
+    let minutes = number(parts.next()?)?;
+    let seconds = number(parts.next()?)?;
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        if digits == 0 {
+            return None;
+        }
+        let padded = format!("{:0<3}", &fraction[..digits.min(3)]);
+        millis = number(&padded)?;
+        rest = &fraction[digits..];
+    }
+
+    let offset_minutes = match rest {
+        "Z" | "z" => 0,
+        _ if rest.len() == 6 && &rest[3..4] == ":" => {
+            let sign = match &rest[..1] {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            sign * (number(&rest[1..3])? * 60 + number(&rest[4..6])?)
+        }
+        _ => return None,
+    };
+
+    Some(Timestamp(
+        date.days_since_epoch() * MILLIS_PER_DAY
+            + ((hours * 60 + minutes - offset_minutes) * 60 + seconds) * 1000
+            + millis,
+    ))
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let millis_of_day = self.0.rem_euclid(MILLIS_PER_DAY);
+        let seconds = millis_of_day / 1000;
+        write!(
+            f,
+            "{}T{:02}:{:02}:{:02}.{:03}Z",
+            self.date(),
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            millis_of_day % 1000
+        )
+    }
+}
+
+#[test]
+fn test_timestamp_parsing() {
+    let parse = |raw: &str| raw.parse::<Timestamp>();
+    assert_eq!(parse("1706659200000"), Ok(Timestamp(1_706_659_200_000)));
+    assert_eq!(
+        parse("2024-01-31T00:00:00Z"),
+        Ok(Timestamp(1_706_659_200_000))
+    );
+    assert_eq!(
+        parse(" 2024-01-31T01:30:00.5+01:30 "),
+        Ok(Timestamp(1_706_659_200_500))
+    );
+    assert_eq!(
+        parse("2024-01-30T23:00:00.123456-01:00"),
+        Ok(Timestamp(1_706_659_200_123))
+    );
+    assert_eq!(parse("2024-01-31"), Err(()));
+    assert_eq!(parse("2024-01-31T00:00:00"), Err(()));
+    assert_eq!(parse("2024-01-31T24:00:00Z"), Err(()));
+    assert_eq!(
+        Timestamp(1_706_659_200_123).to_string(),
+        "2024-01-31T00:00:00.123Z"
+    );
+    assert_eq!(Timestamp(-1).to_string(), "1969-12-31T23:59:59.999Z");
+}
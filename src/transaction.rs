@@ -1,13 +1,50 @@
+use crate::{Date, Timestamp};
 use serde::{
     de::{self, Deserializer, SeqAccess, Visitor},
     Deserialize,
 };
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 pub type ClientId = u16;
 pub type TxId = u32;
+/// Identifies one of a client's wallets. Rows without an `account` are in the client's main
+/// account, [MAIN_ACCOUNT].
+pub type AccountId = u16;
+pub const MAIN_ACCOUNT: AccountId = 0;
 
-/// Represents one transaction from the input CSV.
+/// An ISO 4217 currency code, like `USD` or `EUR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl FromStr for Currency {
+    type Err = ();
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().as_bytes() {
+            &[a, b, c] if [a, b, c].iter().all(u8::is_ascii_alphabetic) => Ok(Currency([
+                a.to_ascii_uppercase(),
+                b.to_ascii_uppercase(),
+                c.to_ascii_uppercase(),
+            ])),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // only ever constructed from ascii letters
+        f.write_str(std::str::from_utf8(&self.0).unwrap())
+    }
+}
+
+/// Represents one transaction from the input CSV. Columns are read by position, in the order
+/// `type, client, tx, amount, currency, original_tx, timestamp, account`. Trailing columns that a
+/// transaction type doesn't use may be left empty or omitted entirely. Any row may have a
+/// timestamp, and any row for a client may name one of their wallets in `account`.
+///
+/// Transactions without a `currency` are in the default, unnamed currency. Disputes, resolutions,
+/// chargebacks, voids, and captures always happen in the currency of the transaction they refer
+/// to, so they have no currency of their own.
 // There are two transaction types that don't have an `amount`. The two options
 // that I think are reasonable for representing this data type are the enum I have below, and
 // something like:
@@ -34,29 +71,278 @@ pub type TxId = u32;
 pub enum Transaction {
     Deposit {
         client: ClientId,
+        account: AccountId,
         tx: TxId,
         amount: f64,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
     },
     Withdrawal {
         client: ClientId,
+        account: AccountId,
         tx: TxId,
         amount: f64,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
     },
     Dispute {
         client: ClientId,
+        account: AccountId,
         tx: TxId,
+        timestamp: Option<Timestamp>,
     },
     Resolve {
         client: ClientId,
+        account: AccountId,
         tx: TxId,
+        timestamp: Option<Timestamp>,
     },
     Chargeback {
         client: ClientId,
+        account: AccountId,
+        tx: TxId,
+        timestamp: Option<Timestamp>,
+    },
+    /// Reserves funds for a later capture, like a card pre-authorization.
+    Authorize {
+        client: ClientId,
+        account: AccountId,
         tx: TxId,
+        amount: f64,
+        currency: Option<Currency>,
+        timestamp: Option<Timestamp>,
+    },
+    /// Settles an earlier authorization. If no amount is given, the full authorized amount is
+    /// captured.
+    Capture {
+        client: ClientId,
+        account: AccountId,
+        tx: TxId,
+        amount: Option<f64>,
+        timestamp: Option<Timestamp>,
+    },
+    /// Releases an earlier authorization without capturing any of it.
+    Void {
+        client: ClientId,
+        account: AccountId,
+        tx: TxId,
+        timestamp: Option<Timestamp>,
+    },
+    /// Returns some or all of an earlier deposit or withdrawal, which is given in the
+    /// `original_tx` column. If a currency is given, it must match the original transaction's.
+    Refund {
+        client: ClientId,
+        account: AccountId,
+        tx: TxId,
+        amount: f64,
+        currency: Option<Currency>,
+        original: TxId,
+        timestamp: Option<Timestamp>,
+    },
+    /// A control row which accrues interest and fees on every balance for the period ending on
+    /// `date`. The date is given in the `client` column.
+    Accrue {
+        date: Date,
+        timestamp: Option<Timestamp>,
     },
     Unrecognized(String),
 }
 
+impl Transaction {
+    /// When the transaction happened, if the input said.
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        use Transaction::*;
+        match self {
+            Deposit { timestamp, .. }
+            | Withdrawal { timestamp, .. }
+            | Dispute { timestamp, .. }
+            | Resolve { timestamp, .. }
+            | Chargeback { timestamp, .. }
+            | Authorize { timestamp, .. }
+            | Capture { timestamp, .. }
+            | Void { timestamp, .. }
+            | Refund { timestamp, .. }
+            | Accrue { timestamp, .. } => *timestamp,
+            Unrecognized(_) => None,
+        }
+    }
+
+    /// The client the row is for, if it is for one.
+    pub fn client(&self) -> Option<ClientId> {
+        use Transaction::*;
+        match self {
+            Deposit { client, .. }
+            | Withdrawal { client, .. }
+            | Dispute { client, .. }
+            | Resolve { client, .. }
+            | Chargeback { client, .. }
+            | Authorize { client, .. }
+            | Capture { client, .. }
+            | Void { client, .. }
+            | Refund { client, .. } => Some(*client),
+            Accrue { .. } | Unrecognized(_) => None,
+        }
+    }
+
+    /// The client and wallet the row is for, if it is for one.
+    pub fn account(&self) -> Option<(ClientId, AccountId)> {
+        use Transaction::*;
+        match self {
+            Deposit {
+                client, account, ..
+            }
+            | Withdrawal {
+                client, account, ..
+            }
+            | Dispute {
+                client, account, ..
+            }
+            | Resolve {
+                client, account, ..
+            }
+            | Chargeback {
+                client, account, ..
+            }
+            | Authorize {
+                client, account, ..
+            }
+            | Capture {
+                client, account, ..
+            }
+            | Void {
+                client, account, ..
+            }
+            | Refund {
+                client, account, ..
+            } => Some((*client, *account)),
+            Accrue { .. } | Unrecognized(_) => None,
+        }
+    }
+
+    /// The transaction id in the `tx` column, if the row has one. For disputes, resolutions,
+    /// chargebacks, captures, and voids this refers to an earlier transaction.
+    pub fn tx(&self) -> Option<TxId> {
+        use Transaction::*;
+        match self {
+            Deposit { tx, .. }
+            | Withdrawal { tx, .. }
+            | Dispute { tx, .. }
+            | Resolve { tx, .. }
+            | Chargeback { tx, .. }
+            | Authorize { tx, .. }
+            | Capture { tx, .. }
+            | Void { tx, .. }
+            | Refund { tx, .. } => Some(*tx),
+            Accrue { .. } | Unrecognized(_) => None,
+        }
+    }
+
+    /// Reads a transaction from its columns, in the same order as the CSV columns, exactly as the
+    /// same row would be read from a file. Trailing columns may be left off.
+    pub fn from_columns<I, S>(columns: I) -> Result<Self, csv::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        csv::StringRecord::from_iter(columns).deserialize(None)
+    }
+
+    /// Reads a transaction from a single CSV row without a header. Empty rows are `None`.
+    pub fn from_line(line: &[u8]) -> Option<Result<Self, csv::Error>> {
+        csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(line)
+            .deserialize()
+            .next()
+    }
+
+    /// Reads a transaction from a CSV record, exactly as deserializing it would. Deposits,
+    /// withdrawals, disputes, resolutions, and chargebacks with nothing after the `amount` column
+    /// are read straight from the record's bytes without allocating, so a reader can reuse one
+    /// [csv::ByteRecord] for every row. Every other row is deserialized as usual.
+    pub fn from_byte_record(record: &csv::ByteRecord) -> Result<Self, csv::Error> {
+        match Self::from_plain_record(record) {
+            Some(transaction) => Ok(transaction),
+            None => record.deserialize(None),
+        }
+    }
+
+    /// The fast path of [Transaction::from_byte_record]. `None` means the row has to be
+    /// deserialized, whether because it is another type, has more columns, or is invalid, so that
+    /// every error comes from one place.
+    fn from_plain_record(record: &csv::ByteRecord) -> Option<Self> {
+        let column = |index| std::str::from_utf8(record.get(index)?).ok().map(str::trim);
+        // deserializing fails on an amount that isn't UTF-8, even for a row that doesn't use it
+        if let Some(amount) = record.get(3) {
+            std::str::from_utf8(amount).ok()?;
+        }
+        // any later column has to be deserialized, even one that is only whitespace
+        if !record.iter().skip(4).all(<[u8]>::is_empty) {
+            return None;
+        }
+        let client = column(1)?.parse().ok()?;
+        let tx = column(2)?.parse().ok()?;
+        let account = MAIN_ACCOUNT;
+        let timestamp = None;
+        Some(match column(0)? {
+            "deposit" => Transaction::Deposit {
+                client,
+                account,
+                tx,
+                amount: column(3)?.parse().ok()?,
+                currency: None,
+                timestamp,
+            },
+            "withdrawal" => Transaction::Withdrawal {
+                client,
+                account,
+                tx,
+                amount: column(3)?.parse().ok()?,
+                currency: None,
+                timestamp,
+            },
+            "dispute" => Transaction::Dispute {
+                client,
+                account,
+                tx,
+                timestamp,
+            },
+            "resolve" => Transaction::Resolve {
+                client,
+                account,
+                tx,
+                timestamp,
+            },
+            "chargeback" => Transaction::Chargeback {
+                client,
+                account,
+                tx,
+                timestamp,
+            },
+            _ => return None,
+        })
+    }
+
+    /// The row's type, as given in the `type` column.
+    pub fn kind(&self) -> &str {
+        use Transaction::*;
+        match self {
+            Deposit { .. } => "deposit",
+            Withdrawal { .. } => "withdrawal",
+            Dispute { .. } => "dispute",
+            Resolve { .. } => "resolve",
+            Chargeback { .. } => "chargeback",
+            Authorize { .. } => "authorize",
+            Capture { .. } => "capture",
+            Void { .. } => "void",
+            Refund { .. } => "refund",
+            Accrue { .. } => "accrue",
+            Unrecognized(kind) => kind,
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for Transaction {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -84,44 +370,100 @@ impl<'de> Deserialize<'de> for Transaction {
                     return Ok(Transaction::Unrecognized(s));
                 }
 
-                let client: String = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let client: ClientId = client
-                    .trim()
-                    .parse()
-                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Unit, &self))?;
-                let tx: String = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                let tx: TxId = tx
-                    .trim()
-                    .parse()
-                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Unit, &self))?;
-
-                // if this is a Dispute or a Resolve, then there is  no amount
+                // Which columns are required depends on the transaction type, so they are all read
+                // up front. Missing and empty columns are both `None`.
+                let client = next_column(&mut seq)?;
+                let tx = next_column(&mut seq)?;
+                let amount = next_column(&mut seq)?;
+                let currency = next_column(&mut seq)?;
+                let original = next_column(&mut seq)?;
+                let timestamp = next_column(&mut seq)?;
+                let account = next_column(&mut seq)?;
+
+                let timestamp: Option<Timestamp> =
+                    parse_optional(timestamp, "an RFC 3339 or epoch millisecond timestamp")?;
+
+                // accruals aren't tied to a client or a transaction, so they look nothing like the
+                // other rows
+                if let TransactionType::Accrue = transaction_type {
+                    let date = parse_required(client, 1, "a date like 2024-01-31")?;
+                    return Ok(Transaction::Accrue { date, timestamp });
+                }
+
+                let client: ClientId = parse_required(client, 1, "a client id")?;
+                let tx: TxId = parse_required(tx, 2, "a transaction id")?;
+                let account: AccountId =
+                    parse_optional(account, "an account id")?.unwrap_or(MAIN_ACCOUNT);
+
+                // if this is a Dispute, Resolve, Chargeback, or Void, then there is no amount
                 use TransactionType::*;
                 Ok(match transaction_type {
-                    Dispute => Transaction::Dispute { client, tx },
-                    Resolve => Transaction::Resolve { client, tx },
-                    Chargeback => Transaction::Chargeback { client, tx },
-                    Deposit => {
-                        let amount: String = seq
-                            .next_element()?
-                            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                        let amount: f64 = amount
-                            .trim()
-                            .parse()
-                            .map_err(|_| de::Error::invalid_value(de::Unexpected::Unit, &self))?;
-                        Transaction::Deposit { client, tx, amount }
-                    }
-                    Withdrawal => {
-                        let amount = seq
-                            .next_element()?
-                            .ok_or_else(|| de::Error::invalid_length(3, &self))?;
-                        Transaction::Withdrawal { client, tx, amount }
-                    }
-                    Unrecognized(_) => unreachable!("this was checked for earlier"),
+                    Dispute => Transaction::Dispute {
+                        client,
+                        account,
+                        tx,
+                        timestamp,
+                    },
+                    Resolve => Transaction::Resolve {
+                        client,
+                        account,
+                        tx,
+                        timestamp,
+                    },
+                    Chargeback => Transaction::Chargeback {
+                        client,
+                        account,
+                        tx,
+                        timestamp,
+                    },
+                    Void => Transaction::Void {
+                        client,
+                        account,
+                        tx,
+                        timestamp,
+                    },
+                    Deposit => Transaction::Deposit {
+                        client,
+                        account,
+                        tx,
+                        amount: parse_required(amount, 3, "an amount")?,
+                        currency: parse_optional(currency, "an ISO 4217 currency code")?,
+                        timestamp,
+                    },
+                    Withdrawal => Transaction::Withdrawal {
+                        client,
+                        account,
+                        tx,
+                        amount: parse_required(amount, 3, "an amount")?,
+                        currency: parse_optional(currency, "an ISO 4217 currency code")?,
+                        timestamp,
+                    },
+                    Authorize => Transaction::Authorize {
+                        client,
+                        account,
+                        tx,
+                        amount: parse_required(amount, 3, "an amount")?,
+                        currency: parse_optional(currency, "an ISO 4217 currency code")?,
+                        timestamp,
+                    },
+                    // a missing amount captures the whole authorization
+                    Capture => Transaction::Capture {
+                        client,
+                        account,
+                        tx,
+                        amount: parse_optional(amount, "an amount")?,
+                        timestamp,
+                    },
+                    Refund => Transaction::Refund {
+                        client,
+                        account,
+                        tx,
+                        amount: parse_required(amount, 3, "an amount")?,
+                        currency: parse_optional(currency, "an ISO 4217 currency code")?,
+                        original: parse_required(original, 5, "a transaction id")?,
+                        timestamp,
+                    },
+                    Accrue | Unrecognized(_) => unreachable!("this was checked for earlier"),
                 })
             }
         }
@@ -129,6 +471,44 @@ impl<'de> Deserialize<'de> for Transaction {
     }
 }
 
+/// Reads the next column. A missing or empty column is `None`.
+fn next_column<'de, V>(seq: &mut V) -> Result<Option<String>, V::Error>
+where
+    V: SeqAccess<'de>,
+{
+    let column: Option<String> = seq.next_element()?;
+    Ok(column.filter(|column| !column.trim().is_empty()))
+}
+
+/// Parses a column the transaction can't do without.
+fn parse_required<T, E>(column: Option<String>, index: usize, expected: &str) -> Result<T, E>
+where
+    T: FromStr,
+    E: de::Error,
+{
+    let column = column.ok_or_else(|| E::invalid_length(index, &expected))?;
+    column
+        .trim()
+        .parse()
+        .map_err(|_| E::invalid_value(de::Unexpected::Str(&column), &expected))
+}
+
+/// Parses a column the transaction may leave empty.
+fn parse_optional<T, E>(column: Option<String>, expected: &str) -> Result<Option<T>, E>
+where
+    T: FromStr,
+    E: de::Error,
+{
+    column
+        .map(|column| {
+            column
+                .trim()
+                .parse()
+                .map_err(|_| E::invalid_value(de::Unexpected::Str(&column), &expected))
+        })
+        .transpose()
+}
+
 #[derive(Debug)]
 enum TransactionType {
     Deposit,
@@ -136,6 +516,11 @@ enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    Refund,
+    Accrue,
     Unrecognized(String),
 }
 
@@ -148,6 +533,11 @@ impl TransactionType {
             "dispute" => Dispute,
             "resolve" => Resolve,
             "chargeback" => Chargeback,
+            "authorize" => Authorize,
+            "capture" => Capture,
+            "void" => Void,
+            "refund" => Refund,
+            "accrue" => Accrue,
             otherwise => Unrecognized(otherwise.into()),
         }
     }
@@ -158,15 +548,25 @@ fn test_transaction_deserialization() {
     let csv = r#"
 type,client,tx,amount
 deposit,1,1,1.0
-deposit,2,2,2.0
-deposit,1,3,2.0
+deposit,2,2,2.0,usd
+deposit,1,3,2.0,
 withdrawal,1,4,1.5
-withdrawal,2,5,3.0
+withdrawal,2,5,3.0,EUR
 dispute,1,6
 resolve,1,7
 foo
 foo,1,2,4,34
-chargeback,100,42"#;
+chargeback,100,42
+authorize,3,8,5.5
+capture,3,8,2.5
+capture,3,9
+capture,3,10,
+void,3,11
+refund,3,12,1.25,,1
+authorize,3,13,1,GBP
+accrue,2024-01-31
+deposit,4,14,1,,,2024-01-31T00:00:00Z
+dispute,4,14,,,,1706659200001,2"#;
     let mut reader = csv::ReaderBuilder::new()
         .flexible(true)
         .from_reader(csv.as_bytes());
@@ -174,43 +574,180 @@ chargeback,100,42"#;
         .deserialize()
         .collect::<Result<Vec<Transaction>, _>>()
         .unwrap();
+    let usd: Currency = "USD".parse().unwrap();
+    let eur: Currency = "EUR".parse().unwrap();
 
     assert_eq!(
         records,
         vec![
             Transaction::Deposit {
                 client: 1,
+                account: 0,
                 tx: 1,
-                amount: 1.0
+                amount: 1.0,
+                currency: None,
+                timestamp: None
             },
             Transaction::Deposit {
                 client: 2,
+                account: 0,
                 tx: 2,
-                amount: 2.0
+                amount: 2.0,
+                currency: Some(usd),
+                timestamp: None
             },
             Transaction::Deposit {
                 client: 1,
+                account: 0,
                 tx: 3,
-                amount: 2.0
+                amount: 2.0,
+                currency: None,
+                timestamp: None
             },
             Transaction::Withdrawal {
                 client: 1,
+                account: 0,
                 tx: 4,
-                amount: 1.5
+                amount: 1.5,
+                currency: None,
+                timestamp: None
             },
             Transaction::Withdrawal {
                 client: 2,
+                account: 0,
                 tx: 5,
-                amount: 3.0
+                amount: 3.0,
+                currency: Some(eur),
+                timestamp: None
+            },
+            Transaction::Dispute {
+                client: 1,
+                account: 0,
+                tx: 6,
+                timestamp: None
+            },
+            Transaction::Resolve {
+                client: 1,
+                account: 0,
+                tx: 7,
+                timestamp: None
             },
-            Transaction::Dispute { client: 1, tx: 6 },
-            Transaction::Resolve { client: 1, tx: 7 },
             Transaction::Unrecognized("foo".into()),
             Transaction::Unrecognized("foo".into()),
             Transaction::Chargeback {
                 client: 100,
-                tx: 42
+                account: 0,
+                tx: 42,
+                timestamp: None
+            },
+            Transaction::Authorize {
+                client: 3,
+                account: 0,
+                tx: 8,
+                amount: 5.5,
+                currency: None,
+                timestamp: None
+            },
+            Transaction::Capture {
+                client: 3,
+                account: 0,
+                tx: 8,
+                amount: Some(2.5),
+                timestamp: None
+            },
+            Transaction::Capture {
+                client: 3,
+                account: 0,
+                tx: 9,
+                amount: None,
+                timestamp: None
+            },
+            Transaction::Capture {
+                client: 3,
+                account: 0,
+                tx: 10,
+                amount: None,
+                timestamp: None
+            },
+            Transaction::Void {
+                client: 3,
+                account: 0,
+                tx: 11,
+                timestamp: None
+            },
+            Transaction::Refund {
+                client: 3,
+                account: 0,
+                tx: 12,
+                amount: 1.25,
+                currency: None,
+                original: 1,
+                timestamp: None
+            },
+            Transaction::Authorize {
+                client: 3,
+                account: 0,
+                tx: 13,
+                amount: 1.0,
+                currency: Some("GBP".parse().unwrap()),
+                timestamp: None
+            },
+            Transaction::Accrue {
+                date: "2024-01-31".parse().unwrap(),
+                timestamp: None
+            },
+            Transaction::Deposit {
+                client: 4,
+                account: 0,
+                tx: 14,
+                amount: 1.0,
+                currency: None,
+                timestamp: Some(Timestamp::from_millis(1_706_659_200_000))
+            },
+            Transaction::Dispute {
+                client: 4,
+                account: 2,
+                tx: 14,
+                timestamp: Some(Timestamp::from_millis(1_706_659_200_001))
             }
         ]
     );
 }
+
+#[test]
+fn test_from_byte_record() {
+    let mut csv = b"type,client,tx,amount
+deposit,1,1,1.0
+ withdrawal , 1 , 2 , 0.5
+deposit,1,3,
+deposit,1,4
+deposit,1,5,2,
+deposit,1,6,2,,,,
+deposit,1,9,2, ,
+deposit,1,7,2,usd
+deposit,70000,8,1
+dispute,1,1,1.0
+dispute,1,1,,,,,2
+resolve,1,1
+chargeback,1,1
+chargeback,1
+void,3,11
+foo,1,2
+"
+    .to_vec();
+    // not UTF-8, in a column a dispute doesn't use
+    csv.extend_from_slice(b"dispute,1,1,\xff\n");
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(csv.as_slice());
+    let mut record = csv::ByteRecord::new();
+    let plain = csv::ByteRecord::from(vec!["deposit", "1", "1", "1.0"]);
+    assert!(Transaction::from_plain_record(&plain).is_some());
+    while reader.read_byte_record(&mut record).unwrap() {
+        let deserialized = record.deserialize::<Transaction>(None);
+        match Transaction::from_byte_record(&record) {
+            Ok(transaction) => assert_eq!(transaction, deserialized.unwrap(), "{:?}", record),
+            Err(_) => assert!(deserialized.is_err(), "{:?}", record),
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! helpers shared by the tests which talk to a server over localhost
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    thread,
+};
+use transactions::State;
+
+/// start a server on a free port with `serve`, which runs until the test ends
+pub fn start(serve: fn(TcpListener, State) -> io::Result<()>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || serve(listener, State::default()));
+    address
+}
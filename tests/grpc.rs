@@ -0,0 +1,139 @@
+//! test the gRPC service with a client in the same process
+#![cfg(feature = "grpc")]
+
+use tokio::net::TcpListener;
+use tokio_stream::StreamExt;
+use tonic::{transport::Channel, Code};
+use transactions::{
+    grpc::{
+        self,
+        proto::{
+            transaction_processor_client::TransactionProcessorClient, Account, GetAccountRequest,
+            SubmitResult, Transaction,
+        },
+    },
+    State,
+};
+
+/// connect to a new server on a free port
+async fn connect() -> TransactionProcessorClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(grpc::serve(listener, State::default()));
+    TransactionProcessorClient::connect(format!("http://{}", address))
+        .await
+        .unwrap()
+}
+
+fn transaction(r#type: &str, client: u32, tx: u32, amount: Option<f64>) -> Transaction {
+    Transaction {
+        r#type: r#type.to_string(),
+        client,
+        tx,
+        amount,
+        ..Default::default()
+    }
+}
+
+fn account(client: u32, available: f64, held: f64, locked: bool) -> Account {
+    Account {
+        client,
+        account: Some(0),
+        available,
+        held,
+        total: available + held,
+        locked,
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn submit_and_query() {
+    let mut client = connect().await;
+    let applied = SubmitResult {
+        applied: true,
+        error: None,
+    };
+    let result = client
+        .submit_transaction(transaction("deposit", 1, 1, Some(10.)))
+        .await
+        .unwrap();
+    assert_eq!(result.into_inner(), applied);
+
+    let batch = tokio_stream::iter(vec![
+        transaction("deposit", 2, 2, Some(3.)),
+        transaction("dispute", 2, 1, None),
+        transaction("withdrawal", 1, 3, None),
+        transaction("dispute", 1, 1, None),
+    ]);
+    let results = client
+        .submit_transactions(batch)
+        .await
+        .unwrap()
+        .into_inner()
+        .collect::<Result<Vec<_>, _>>()
+        .await
+        .unwrap();
+    let rejected = |error: &str| SubmitResult {
+        applied: false,
+        error: Some(error.to_string()),
+    };
+    assert_eq!(
+        results,
+        vec![
+            applied.clone(),
+            rejected("transaction 1 belongs to another client"),
+            rejected("could not read the transaction"),
+            applied.clone(),
+        ]
+    );
+
+    let report = client
+        .get_account(GetAccountRequest { client: 1 })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(report.accounts, vec![account(1, 0., 10., false)]);
+    let unknown = client
+        .get_account(GetAccountRequest { client: 3 })
+        .await
+        .unwrap_err();
+    assert_eq!(unknown.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn watch_account() {
+    let mut client = connect().await;
+    let mut reports = client
+        .watch_account(GetAccountRequest { client: 1 })
+        .await
+        .unwrap()
+        .into_inner();
+    // the client isn't known yet
+    assert_eq!(reports.next().await.unwrap().unwrap().accounts, vec![]);
+
+    let mut locked = account(1, 0., 0., true);
+    locked.lock_reason = Some("chargeback:1".to_string());
+    // each report is waited for before the next change, so none are skipped
+    for (transactions, expected) in [
+        (
+            vec![transaction("deposit", 1, 1, Some(10.))],
+            account(1, 10., 0., false),
+        ),
+        (
+            // changing another client sends nothing
+            vec![
+                transaction("deposit", 2, 2, Some(5.)),
+                transaction("dispute", 1, 1, None),
+            ],
+            account(1, 0., 10., false),
+        ),
+        (vec![transaction("chargeback", 1, 1, None)], locked),
+    ] {
+        for transaction in transactions {
+            client.submit_transaction(transaction).await.unwrap();
+        }
+        let report = reports.next().await.unwrap().unwrap();
+        assert_eq!(report.accounts, vec![expected]);
+    }
+}
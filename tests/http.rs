@@ -0,0 +1,113 @@
+//! test the HTTP API over localhost
+#![cfg(feature = "http")]
+
+use serde_json::{json, Value};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+};
+use transactions::*;
+
+mod common;
+use common::start;
+
+/// make a request, and return the status code and the JSON body
+fn request(address: SocketAddr, method: &str, path: &str, body: Option<Value>) -> (u16, Value) {
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn submit_and_query() {
+    let address = start(http::serve);
+    assert_eq!(
+        request(
+            address,
+            "POST",
+            "/transactions",
+            Some(json!({"type": "deposit", "client": 1, "tx": 1, "amount": 10.5})),
+        ),
+        (200, json!({"applied": true}))
+    );
+    assert_eq!(
+        request(
+            address,
+            "POST",
+            "/transactions",
+            Some(json!([
+                {"type": "deposit", "client": 2, "tx": 2, "amount": "3"},
+                {"type": "dispute", "client": 2, "tx": 1},
+                {"type": "dispute", "client": 1, "tx": 1},
+                {"type": "withdrawal", "client": 1},
+            ])),
+        ),
+        (
+            200,
+            json!([
+                {"applied": true},
+                {"applied": false, "error": "transaction 1 belongs to another client"},
+                {"applied": true},
+                {"applied": false, "error": "could not read the transaction"},
+            ])
+        )
+    );
+    assert_eq!(
+        request(address, "POST", "/transactions", None).0,
+        400,
+        "an empty body is not JSON"
+    );
+
+    let account = |client, available, held: f64| {
+        json!({
+            "client": client,
+            "account": 0,
+            "currency": null,
+            "available": available,
+            "held": held,
+            "total": available + held,
+            "locked": false,
+            "lock_reason": null,
+        })
+    };
+    assert_eq!(
+        request(address, "GET", "/accounts", None),
+        (200, json!([account(1, 0., 10.5), account(2, 3., 0.)]))
+    );
+    assert_eq!(
+        request(address, "GET", "/accounts/2", None),
+        (200, json!([account(2, 3., 0.)]))
+    );
+    assert_eq!(request(address, "GET", "/accounts/3", None).0, 404);
+
+    assert_eq!(
+        request(address, "GET", "/transactions/1", None),
+        (
+            200,
+            json!({
+                "tx": 1,
+                "client": 1,
+                "account": 0,
+                "amount": 10.5,
+                "currency": null,
+                "disputable": true,
+                "dispute": "disputed",
+            })
+        )
+    );
+    assert_eq!(request(address, "GET", "/transactions/9", None).0, 404);
+    assert_eq!(request(address, "DELETE", "/transactions/1", None).0, 405);
+}
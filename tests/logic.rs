@@ -1,21 +1,50 @@
-use transactions::{State, Transaction};
+use transactions::{
+    accrual::{AccrualConfig, DayCount},
+    blocklist::Blocklist,
+    fx::RateTable,
+    reorder::ReorderBuffer,
+    rules::{Outcome, Rule, RuleSet},
+    AsOf, Balance, Clock, DisputePolicy, Event, LockPolicy, LockScope, LockedAccountPolicy, State,
+    Transaction, TransactionError, Window,
+};
+
+/// Reads a single row without a header.
+fn row_of(line: &str) -> Transaction {
+    Transaction::from_line(line.as_bytes()).unwrap().unwrap()
+}
 
 /// Given an input list of transactions, run it through the state machine and assess the output.
 fn harness(input: &str, expected_output: &str) -> bool {
-    let mut reader = csv::ReaderBuilder::new()
-        .flexible(true)
-        .from_reader(input.as_bytes());
-
-    let mut state: State = Default::default();
+    harness_with_state(Default::default(), input, expected_output)
+}
 
-    let _records = reader
+/// Reads the transactions from an input with a header.
+fn rows(input: &str) -> Vec<Transaction> {
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(input.as_bytes())
         .deserialize()
-        .collect::<Result<Vec<Transaction>, _>>()
+        .collect::<Result<_, _>>()
         .unwrap()
+}
+
+/// Runs an input through the state machine, ignoring rejected rows.
+fn run(mut state: State, input: &str) -> State {
+    rows(input).into_iter().for_each(|tx| state.transact(tx));
+    state
+}
+
+/// Like [run], but returns the result of each row.
+fn try_run(state: &mut State, input: &str) -> Vec<Result<(), TransactionError>> {
+    rows(input)
         .into_iter()
-        .for_each(|tx| state.transact(tx));
+        .map(|tx| state.try_transact(tx))
+        .collect()
+}
 
-    let output = match state.serialize_to_csv() {
+/// Like [harness], but starting from a preconfigured state.
+fn harness_with_state(state: State, input: &str, expected_output: &str) -> bool {
+    let output = match run(state, input).serialize_to_csv() {
         Ok(o) => o,
         Err(_) => return false,
     };
@@ -71,6 +100,149 @@ chargeback,1,1"#,
     ));
 }
 
+/// A transaction can only be under one dispute at a time, so disputing it again holds nothing more.
+#[test]
+fn double_dispute() {
+    assert!(harness(
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+dispute,1,1
+dispute,1,1"#,
+        r#"client,available,held,total,locked
+1,0,10,10,false
+"#
+    ));
+}
+
+/// Resolving a transaction that isn't disputed releases nothing.
+#[test]
+fn resolve_without_dispute() {
+    assert!(harness(
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,5
+dispute,1,2
+resolve,1,1"#,
+        r#"client,available,held,total,locked
+1,10,5,15,false
+"#
+    ));
+}
+
+/// Once charged back, a transaction can't be disputed, resolved, or charged back again.
+#[test]
+fn repeated_chargeback() {
+    let mut state = State::default().with_lock_policy(LockPolicy::AfterChargebacks(2));
+    for row in [
+        "deposit,1,1,10",
+        "deposit,1,2,5",
+        "dispute,1,1",
+        "chargeback,1,1",
+    ] {
+        state.try_transact(row_of(row)).unwrap();
+    }
+    assert_eq!(
+        state.try_transact(row_of("dispute,1,1")),
+        Err(TransactionError::ChargedBack(1))
+    );
+    assert_eq!(
+        state.try_transact(row_of("resolve,1,1")),
+        Err(TransactionError::NotDisputed(1))
+    );
+    assert_eq!(
+        state.try_transact(row_of("chargeback,1,1")),
+        Err(TransactionError::ChargedBack(1))
+    );
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,5,0,5,false\n"
+    );
+}
+
+/// A second transaction with an id already used is rejected, and the first can still be disputed
+/// and refunded.
+#[test]
+fn duplicate_tx_id() {
+    let mut state = State::default();
+    state.try_transact(row_of("deposit,1,1,10")).unwrap();
+    for row in ["deposit,1,1,20", "withdrawal,1,1,3", "refund,1,1,2,,1"] {
+        assert_eq!(
+            state.try_transact(row_of(row)),
+            Err(TransactionError::DuplicateTransaction(1))
+        );
+    }
+    state.try_transact(row_of("refund,1,2,4,,1")).unwrap();
+    assert_eq!(
+        state.try_transact(row_of("refund,1,2,1,,1")),
+        Err(TransactionError::DuplicateTransaction(2))
+    );
+    state.try_transact(row_of("dispute,1,1")).unwrap();
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,0,6,6,false\n"
+    );
+}
+
+/// Only what hasn't been refunded can be disputed and charged back, so refunded money is never
+/// returned twice.
+#[test]
+fn chargeback_after_refund() {
+    // a partly refunded deposit only sends back what the client still has
+    let mut state = State::default();
+    for row in [
+        "deposit,1,1,10",
+        "refund,1,2,4,,1",
+        "dispute,1,1",
+        "chargeback,1,1",
+    ] {
+        state.try_transact(row_of(row)).unwrap();
+    }
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,0,0,0,true\n"
+    );
+
+    // and a partly refunded withdrawal only credits what the client hasn't had back
+    let mut state = State::default();
+    for row in [
+        "deposit,1,1,10",
+        "withdrawal,1,2,4",
+        "refund,1,3,1,,2",
+        "dispute,1,2",
+        "chargeback,1,2",
+    ] {
+        state.try_transact(row_of(row)).unwrap();
+    }
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,10,0,10,true\n"
+    );
+
+    // nothing is left of a transaction refunded in full
+    for (rows, tx) in [
+        (&["deposit,1,1,10", "refund,1,2,10,,1"][..], 1),
+        (
+            &["deposit,1,1,10", "withdrawal,1,2,4", "refund,1,3,4,,2"],
+            2,
+        ),
+    ] {
+        let mut state = State::default();
+        for row in rows {
+            state.try_transact(row_of(row)).unwrap();
+        }
+        assert_eq!(
+            state.try_transact(row_of(&format!("dispute,1,{}", tx))),
+            Err(TransactionError::Refunded(tx))
+        );
+        assert_eq!(
+            state.try_transact(row_of(&format!("chargeback,1,{}", tx))),
+            Err(TransactionError::NotDisputed(tx))
+        );
+    }
+}
+
 /// If a dispute references a client id that is not part of the transaction it refers to, it should
 /// be a noop
 #[test]
@@ -104,3 +276,802 @@ chargeback,1,2"#,
 "#
     ));
 }
+
+/// Every kind of balance movement should leave the ledger balanced.
+#[test]
+fn trial_balance_nets_to_zero() {
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,10.25
+deposit,2,2,3.1
+withdrawal,1,3,2.2
+dispute,1,1
+chargeback,1,1
+withdrawal,2,4,1.7
+dispute,2,4
+chargeback,2,4
+deposit,2,5,0.3
+dispute,2,5
+resolve,2,5"#;
+    let state = run(Default::default(), input);
+
+    let trial_balance = state.trial_balance();
+    assert!(trial_balance.is_balanced());
+}
+
+/// Authorized funds are held until they are captured, and only the captured amount leaves.
+#[test]
+fn partial_capture() {
+    assert!(harness(
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+authorize,1,2,6
+capture,1,2,4
+capture,1,2,1"#,
+        r#"client,available,held,total,locked
+1,6,0,6,false
+"#
+    ));
+}
+
+/// Capturing more than was authorized is invalid, and voiding releases the funds.
+#[test]
+fn over_capture_then_void() {
+    assert!(harness(
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+authorize,1,2,6
+capture,1,2,7
+void,1,2
+capture,1,2"#,
+        r#"client,available,held,total,locked
+1,10,0,10,false
+"#
+    ));
+}
+
+/// Pending authorizations are released once they expire, and can no longer be captured.
+#[test]
+fn authorization_expiry() {
+    assert!(harness_with_state(
+        State::default().with_authorization_expiry(2),
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+authorize,1,2,6
+capture,1,2
+authorize,1,3,1
+deposit,1,4,2
+capture,1,3"#,
+        r#"client,available,held,total,locked
+1,6,0,6,false
+"#
+    ));
+}
+
+/// A captured authorization is a withdrawal, so it can be disputed and charged back.
+#[test]
+fn chargeback_captured_authorization() {
+    assert!(harness(
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+authorize,1,2,6
+dispute,1,2
+capture,1,2
+dispute,1,2
+chargeback,1,2"#,
+        r#"client,available,held,total,locked
+1,10,0,10,true
+"#
+    ));
+}
+
+/// Refunds can't exceed what is left of the original transaction, or reference another client's.
+#[test]
+fn refund_limits() {
+    assert!(harness(
+        r#"
+type,client,tx,amount,currency,original_tx
+deposit,1,1,10
+withdrawal,1,2,4
+refund,1,3,3,,2
+refund,1,4,2,,2
+refund,2,5,1,,2
+refund,1,6,1,,2
+refund,1,7,6,,1"#,
+        r#"client,available,held,total,locked
+1,4,0,4,false
+"#
+    ));
+}
+
+/// A refund that credited the client is disputed like a deposit.
+#[test]
+fn dispute_refund() {
+    assert!(harness(
+        r#"
+type,client,tx,amount,currency,original_tx
+deposit,1,1,10
+withdrawal,1,2,4
+refund,1,3,4,,2
+dispute,1,3
+refund,1,4,1,,3"#,
+        r#"client,available,held,total,locked
+1,6,4,10,false
+"#
+    ));
+}
+
+/// Each currency is its own balance, and disputes happen in the currency of the disputed
+/// transaction.
+#[test]
+fn multi_currency() {
+    assert!(harness(
+        r#"
+type,client,tx,amount,currency
+deposit,1,1,10,USD
+deposit,1,2,5,eur
+deposit,1,3,1
+withdrawal,1,4,2,USD
+dispute,1,2
+deposit,2,5,7,USD
+refund,2,6,1,EUR,5
+refund,2,7,1,,5"#,
+        r#"client,available,held,total,locked,currency
+1,1,0,1,false,
+1,0,5,5,false,EUR
+1,8,0,8,false,USD
+2,6,0,6,false,USD
+"#
+    ));
+}
+
+/// Foreign-currency transactions are converted into the base currency, and reversed at the rate
+/// they were booked at.
+#[test]
+fn fx_conversion() {
+    let rates = r#"date,pair,rate
+2024-01-01,EUR/USD,1.5
+2024-01-01,USD/JPY,150
+"#;
+    let rates = RateTable::from_reader(rates.as_bytes()).unwrap();
+    assert!(harness_with_state(
+        State::default().with_fx("USD".parse().unwrap(), rates),
+        r#"
+type,client,tx,amount,currency
+deposit,1,1,10,EUR
+deposit,1,2,1000,JPY
+deposit,1,3,1
+withdrawal,1,4,1,GBP
+dispute,1,2
+refund,1,5,2,EUR,1
+refund,1,6,2,EUR,2"#,
+        r#"client,available,held,total,locked,currency
+1,13,6.6667,19.6667,false,USD
+"#
+    ));
+}
+
+/// A timestamped row is converted at the rate for its date, not the newest rate in the table.
+/// Rows without a timestamp use the date of the latest timestamp seen, or the newest rate if
+/// there hasn't been one.
+#[test]
+fn fx_conversion_by_date() {
+    let rates = r#"date,pair,rate
+2024-01-01,EUR/USD,1.5
+2024-12-01,EUR/USD,2
+"#;
+    let rates = RateTable::from_reader(rates.as_bytes()).unwrap();
+    assert!(harness_with_state(
+        State::default().with_fx("USD".parse().unwrap(), rates),
+        r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,EUR
+deposit,2,2,10,EUR,,2023-12-31T00:00:00Z
+deposit,2,3,10,EUR,,2024-01-31T00:00:00Z
+deposit,3,4,10,EUR
+deposit,3,5,10,EUR,,2024-12-01T00:00:00Z"#,
+        r#"client,available,held,total,locked,currency
+1,20,0,20,false,USD
+2,15,0,15,false,USD
+3,35,0,35,false,USD
+"#
+    ));
+}
+
+/// Interest is paid on positive available balances and charged on overdrawn ones, and the first
+/// accrual only starts the first period.
+#[test]
+fn interest_accrual() {
+    let config = AccrualConfig {
+        credit_rate: 0.0365,
+        overdraft_rate: 0.365,
+        period_fee: 0.5,
+        day_count: DayCount::Actual365,
+    };
+    let input = r#"
+type,client,tx,amount
+accrue,2024-01-01
+deposit,1,1,1000
+withdrawal,2,2,100
+deposit,3,3,50
+dispute,3,3
+accrue,2024-01-11
+accrue,2024-01-05"#;
+    let state = run(State::default().with_accrual(config), input);
+    assert!(state.trial_balance().is_balanced());
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,1000.5,0,1000.5,false
+2,-101.5,0,-101.5,false
+3,-0.5,50,49.5,false
+"#
+    );
+}
+
+/// Rows timestamped too far behind the latest timestamp are rejected, and rows without a
+/// timestamp are always accepted.
+#[test]
+fn timestamps_going_backwards() {
+    assert!(harness_with_state(
+        State::default().with_clock_tolerance(60_000),
+        r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,,,2024-01-31T12:00:00Z
+deposit,1,2,1,,,2024-01-31T11:59:30Z
+deposit,1,3,100,,,2024-01-31T11:58:59Z
+deposit,1,4,1000
+withdrawal,1,5,5,,,1706702400000"#,
+        r#"client,available,held,total,locked
+1,1006,0,1006,false
+"#
+    ));
+}
+
+/// The clock counts every row, and keeps the latest timestamp.
+#[test]
+fn clock_advances() {
+    let input = r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,,,2024-01-31T12:00:00Z
+deposit,1,2,1,,,2024-01-31T11:00:00Z
+deposit,1,3,1"#;
+    let state = run(State::default(), input);
+    assert_eq!(
+        state.clock(),
+        Clock {
+            rows: 3,
+            time: Some("2024-01-31T12:00:00Z".parse().unwrap())
+        }
+    );
+}
+
+/// Transactions recorded longer ago than the filing window can't be disputed.
+#[test]
+fn dispute_filing_window() {
+    let policy = DisputePolicy {
+        filing_window: Some(Window::rows(2)),
+        expiry: None,
+    };
+    assert!(harness_with_state(
+        State::default().with_dispute_policy(policy),
+        r#"
+type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,5
+dispute,1,2
+dispute,1,1"#,
+        r#"client,available,held,total,locked
+1,10,5,15,false
+"#
+    ));
+}
+
+/// Disputes left open past the expiry are resolved in the client's favour, unless they were
+/// charged back first.
+#[test]
+fn dispute_expiry() {
+    let policy = DisputePolicy {
+        filing_window: None,
+        expiry: Some(Window::days(30)),
+    };
+    let input = r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,,,2024-01-01T00:00:00Z
+deposit,2,2,20,,,2024-01-01T00:00:00Z
+dispute,1,1,,,,2024-01-02T00:00:00Z
+dispute,2,2,,,,2024-01-02T00:00:00Z
+chargeback,2,2,,,,2024-01-03T00:00:00Z
+deposit,3,3,1,,,2024-02-01T00:00:01Z"#;
+    let mut state = run(State::default().with_dispute_policy(policy), input);
+    assert_eq!(
+        state.take_events(),
+        vec![Event::DisputeExpired {
+            client: 1,
+            tx: 1,
+            at: Clock {
+                rows: 6,
+                time: Some("2024-02-01T00:00:01Z".parse().unwrap())
+            }
+        }]
+    );
+    assert!(state.take_events().is_empty());
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,10,0,10,false
+2,0,0,0,true
+3,1,0,1,false
+"#
+    );
+}
+
+/// Disputes that arrive before the transaction they refer to are held back until it arrives, and
+/// reported if it never does.
+#[test]
+fn reordered_dispute() {
+    let input = r#"
+type,client,tx,amount
+dispute,1,1
+chargeback,1,1
+deposit,1,1,10
+deposit,2,2,5
+dispute,2,3
+deposit,2,4,1
+deposit,2,5,1
+deposit,2,3,100"#;
+    let mut state = State::default();
+    let mut reorder = ReorderBuffer::new(2);
+    for tx in rows(input) {
+        reorder.push(&mut state, tx);
+    }
+    let unmatched = reorder.finish();
+    assert_eq!(unmatched.len(), 1);
+    assert_eq!(unmatched[0].tx(), Some(3));
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,0,0,0,true
+2,107,0,107,false
+"#
+    );
+}
+
+/// Balances can be reported as they were at an earlier row or time.
+#[test]
+fn as_of_report() {
+    let input = r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,,,2024-01-01T09:00:00Z
+deposit,1,2,5,,,2024-01-01T10:00:00Z
+dispute,1,1,,,,2024-01-02T09:00:00Z
+deposit,2,3,1
+chargeback,1,1,,,,2024-01-03T09:00:00Z"#;
+    let state = run(State::default().with_checkpoints(2), input);
+    let report = |as_of| state.as_of(as_of).serialize_to_csv().unwrap();
+
+    assert_eq!(report(AsOf::Row(0)), "client,available,held,total,locked\n");
+    assert_eq!(
+        report(AsOf::Row(3)),
+        r#"client,available,held,total,locked
+1,5,10,15,false
+"#
+    );
+    assert_eq!(
+        report(AsOf::Time("2024-01-02T23:59:59Z".parse().unwrap())),
+        r#"client,available,held,total,locked
+1,5,10,15,false
+2,1,0,1,false
+"#
+    );
+    assert_eq!(
+        report(AsOf::Row(100)),
+        r#"client,available,held,total,locked
+1,5,0,5,true
+2,1,0,1,false
+"#
+    );
+}
+
+/// Rebuilding works whether or not the rows end on a checkpoint, and keeping only what a known
+/// cutoff needs gives the same report as keeping everything.
+#[test]
+fn as_of_exact_multiple() {
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,10
+deposit,1,2,5
+dispute,1,1,
+deposit,2,3,1
+chargeback,1,1,"#;
+    let after = |state: State| run(state, input);
+    let everything = after(State::default()).serialize_to_csv().unwrap();
+    let three_rows = r#"client,available,held,total,locked
+1,5,10,15,false
+"#;
+
+    for every in [1, 5] {
+        let state = after(State::default().with_checkpoints(every));
+        for (as_of, expected) in [
+            (AsOf::Row(5), everything.as_str()),
+            (AsOf::Row(100), &everything),
+            (AsOf::Row(3), three_rows),
+        ] {
+            assert_eq!(
+                state.as_of(as_of).serialize_to_csv().unwrap(),
+                expected,
+                "{every}"
+            );
+            let state = after(State::default().with_checkpoints_for(every, as_of));
+            assert_eq!(
+                state.as_of(as_of).serialize_to_csv().unwrap(),
+                expected,
+                "{every}"
+            );
+        }
+    }
+}
+
+/// Rows which break a rule are flagged, rejected, or lock the account, depending on the rule.
+#[test]
+fn rules_engine() {
+    let mut rules = RuleSet::default();
+    rules.push(Rule::MaxDeposit { amount: 100. }, Outcome::Flag);
+    rules.push(
+        Rule::MaxWithdrawals {
+            count: 1,
+            window: Window::rows(10),
+        },
+        Outcome::Reject,
+    );
+    rules.push(Rule::MaxDisputePercent { percent: 50. }, Outcome::Lock);
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,500
+withdrawal,1,2,10
+withdrawal,1,3,10
+deposit,2,4,5
+deposit,2,5,5
+dispute,2,4
+dispute,2,5
+deposit,2,6,1"#;
+    let mut state = State::default().with_rules(rules);
+    let results = try_run(&mut state, input);
+    assert_eq!(results[0], Ok(()));
+    assert_eq!(results[2], Err(TransactionError::Rule("max_withdrawals")));
+    assert_eq!(
+        results[6],
+        Err(TransactionError::Rule("max_dispute_percent"))
+    );
+    assert_eq!(results[7], Err(TransactionError::Locked(2)));
+    let broken = state
+        .take_events()
+        .into_iter()
+        .map(|event| match event {
+            Event::RuleBroken { rule, outcome, .. } => (rule, outcome),
+            event => panic!("unexpected event {:?}", event),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        broken,
+        vec![
+            ("max_deposit", Outcome::Flag),
+            ("max_withdrawals", Outcome::Reject),
+            ("max_dispute_percent", Outcome::Lock),
+        ]
+    );
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,490,0,490,false
+2,5,5,10,true
+"#
+    );
+}
+
+/// A row which would be rejected anyway breaks no rules, so it can't lock the account or raise an
+/// alert.
+#[test]
+fn rules_skip_rejected_rows() {
+    let mut rules = RuleSet::default();
+    rules.push(Rule::MaxDisputePercent { percent: 50. }, Outcome::Lock);
+    let mut state = State::default().with_rules(rules);
+    state.try_transact(row_of("deposit,1,1,10")).unwrap();
+    for (row, error) in [
+        ("dispute,1,999", TransactionError::UnknownTransaction(999)),
+        ("dispute,2,1", TransactionError::WrongClient(1)),
+    ] {
+        assert_eq!(state.try_transact(row_of(row)), Err(error));
+    }
+    assert!(state.take_events().is_empty());
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        "client,available,held,total,locked\n1,10,0,10,false\n"
+    );
+}
+
+/// Blocked clients can't deposit or withdraw, but their disputes still go ahead, and the
+/// blocklist can be replaced part way through.
+#[test]
+fn blocklist() {
+    let mut blocklist = Blocklist::default();
+    blocklist.insert(1, "sanctions");
+    let mut state = State::default();
+    try_run(&mut state, "type,client,tx,amount\ndeposit,1,1,10");
+
+    state.set_blocklist(blocklist);
+    let results = try_run(
+        &mut state,
+        "type,client,tx,amount\ndeposit,1,2,5\ndispute,1,1\nwithdrawal,2,3,1",
+    );
+    assert_eq!(
+        results,
+        vec![
+            Err(TransactionError::Blocked {
+                client: 1,
+                reason: "sanctions".to_string()
+            }),
+            Ok(()),
+            Ok(()),
+        ]
+    );
+
+    state.set_blocklist(Blocklist::default());
+    try_run(&mut state, "type,client,tx,amount\ndeposit,1,4,1");
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,1,10,11,false
+2,-1,0,-1,false
+"#
+    );
+}
+
+/// With the credit chargebacks policy, a chargeback of a withdrawal doesn't lock the account, and
+/// the extended output says why accounts were locked.
+#[test]
+fn lock_policy() {
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,10
+withdrawal,1,2,4
+dispute,1,2
+chargeback,1,2
+deposit,2,3,10
+dispute,2,3
+chargeback,2,3"#;
+    let state = run(
+        State::default().with_lock_policy(LockPolicy::CreditChargebacks),
+        input,
+    );
+    assert_eq!(
+        state.serialize_extended_to_csv().unwrap(),
+        r#"client,available,held,total,locked,lock_reason
+1,10,0,10,false,
+2,0,0,0,true,chargeback:3
+"#
+    );
+}
+
+/// Deposits rejected because the account is locked aren't recorded, so they can't be disputed,
+/// and the locked account policy decides what a locked client may still do.
+#[test]
+fn locked_account_policy() {
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,10
+dispute,1,1
+chargeback,1,1
+deposit,1,2,5
+dispute,1,2
+deposit,2,3,10
+dispute,2,3
+chargeback,2,3
+deposit,2,4,5
+withdrawal,2,5,1"#;
+    let policy = LockedAccountPolicy {
+        deposit: true,
+        ..Default::default()
+    };
+    let mut state = State::default();
+    let mut with_policy = State::default().with_locked_account_policy(policy);
+    let results = rows(input)
+        .into_iter()
+        .map(|tx| match tx.client() {
+            Some(1) => state.try_transact(tx),
+            _ => with_policy.try_transact(tx),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(results[3], Err(TransactionError::Locked(1)));
+    assert_eq!(results[4], Err(TransactionError::UnknownTransaction(2)));
+    assert_eq!(results[8], Ok(()));
+    assert_eq!(results[9], Err(TransactionError::Locked(2)));
+    assert_eq!(
+        state.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+1,0,0,0,true
+"#
+    );
+    assert_eq!(
+        with_policy.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked
+2,5,0,5,true
+"#
+    );
+}
+
+/// Transactions must refer to the wallet they were made in, and wallets can be locked on their
+/// own, in which case the client as a whole is not locked.
+#[test]
+fn wallets() {
+    let input = r#"
+type,client,tx,amount,currency,original_tx,timestamp,account
+deposit,1,1,10,,,,
+deposit,1,2,5,,,,1
+withdrawal,1,3,2,,,,1
+dispute,1,1,,,,,1
+dispute,1,1,,,,,0
+chargeback,1,1,,,,,0
+deposit,1,4,1,,,,1
+deposit,1,5,1,,,,0"#;
+    let mut client_scope = State::default();
+    let mut wallet_scope = State::default().with_lock_scope(LockScope::Wallet);
+    let mut results = vec![];
+    for tx in rows(input) {
+        client_scope.transact(tx.clone());
+        results.push(wallet_scope.try_transact(tx));
+    }
+    assert_eq!(results[3], Err(TransactionError::WrongAccount(1)));
+    assert_eq!(results[6], Ok(()));
+    assert_eq!(results[7], Err(TransactionError::Locked(1)));
+    assert_eq!(
+        client_scope.serialize_to_csv().unwrap(),
+        r#"client,available,held,total,locked,account
+1,0,0,0,true,0
+1,3,0,3,true,1
+1,3,0,3,true,all
+"#
+    );
+    assert_eq!(
+        wallet_scope.serialize_extended_to_csv().unwrap(),
+        r#"client,available,held,total,locked,account,lock_reason
+1,0,0,0,true,0,chargeback:1
+1,4,0,4,false,1,
+1,4,0,4,false,all,
+"#
+    );
+}
+
+/// Every row for a client records how it changed the client's wallet, including rows which
+/// were rejected and changed nothing.
+#[test]
+fn changes() {
+    let input = r#"
+type,client,tx,amount
+deposit,1,1,10
+dispute,2,1
+dispute,1,1
+chargeback,1,1
+deposit,1,2,5
+accrue,2024-01-31"#;
+    let mut state = run(State::default().with_changes(), input);
+    let changes = state.take_changes();
+    assert_eq!(changes.len(), 5);
+    let balance = |held, total, locked| Balance {
+        held,
+        total,
+        locked,
+    };
+    let summary = changes
+        .iter()
+        .map(|change| {
+            (
+                change.cause.kind(),
+                change.result.clone(),
+                change.before,
+                change.after,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "deposit",
+                Ok(()),
+                balance(0., 0., false),
+                balance(0., 10., false)
+            ),
+            (
+                "dispute",
+                Err(TransactionError::WrongClient(1)),
+                balance(0., 0., false),
+                balance(0., 0., false)
+            ),
+            (
+                "dispute",
+                Ok(()),
+                balance(0., 10., false),
+                balance(10., 10., false)
+            ),
+            (
+                "chargeback",
+                Ok(()),
+                balance(10., 10., false),
+                balance(0., 0., true)
+            ),
+            (
+                "deposit",
+                Err(TransactionError::Locked(1)),
+                balance(0., 0., true),
+                balance(0., 0., true)
+            ),
+        ]
+    );
+    assert_eq!(changes[4].at.rows, 5);
+    assert!(state.take_changes().is_empty());
+}
+
+/// Expiries and accruals record their own changes, and a row's change starts from the balances
+/// left after anything which expired by its time.
+#[test]
+fn changes_made_by_the_state() {
+    let input = r#"
+type,client,tx,amount,currency,original_tx,timestamp
+deposit,1,1,10,,,2024-01-01T00:00:00Z
+dispute,1,1,,,,2024-01-02T00:00:00Z
+deposit,2,2,5,,,2024-01-02T00:00:00Z
+authorize,2,3,4,,,2024-01-02T00:00:00Z
+accrue,2024-01-31
+deposit,1,4,1,,,2024-02-02T00:00:00Z
+accrue,2024-02-29"#;
+    let mut state = run(
+        State::default()
+            .with_changes()
+            .with_authorization_expiry(2)
+            .with_dispute_policy(DisputePolicy {
+                filing_window: None,
+                expiry: Some(Window::days(30)),
+            })
+            .with_accrual(AccrualConfig {
+                period_fee: 1.,
+                ..Default::default()
+            }),
+        input,
+    );
+    let summary = state
+        .take_changes()
+        .iter()
+        .map(|change| {
+            (
+                change.at.rows,
+                change.cause.kind().to_string(),
+                change.cause.tx(),
+                change.client,
+                (change.before.held, change.before.total),
+                (change.after.held, change.after.total),
+            )
+        })
+        .collect::<Vec<_>>();
+    let change = |row, kind: &str, tx, client, before, after| {
+        (row, kind.to_string(), tx, client, before, after)
+    };
+    assert_eq!(
+        summary,
+        vec![
+            change(1, "deposit", Some(1), 1, (0., 0.), (0., 10.)),
+            change(2, "dispute", Some(1), 1, (0., 10.), (10., 10.)),
+            change(3, "deposit", Some(2), 2, (0., 0.), (0., 5.)),
+            change(4, "authorize", Some(3), 2, (0., 5.), (4., 5.)),
+            change(6, "authorization_expired", Some(3), 2, (4., 5.), (0., 5.)),
+            change(6, "dispute_expired", Some(1), 1, (10., 10.), (0., 10.)),
+            change(6, "deposit", Some(4), 1, (0., 10.), (0., 11.)),
+            change(7, "accrual", None, 1, (0., 11.), (0., 10.)),
+            change(7, "accrual", None, 2, (0., 5.), (0., 4.)),
+        ]
+    );
+}
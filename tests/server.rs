@@ -0,0 +1,92 @@
+//! test the line protocol over localhost
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+};
+use transactions::*;
+
+mod common;
+use common::start;
+
+/// a connection to the server, which sends a line and reads the reply
+struct Connection {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+    fn open(address: SocketAddr) -> Self {
+        let writer = TcpStream::connect(address).unwrap();
+        writer.set_nodelay(true).unwrap();
+        let reader = BufReader::new(writer.try_clone().unwrap());
+        Connection { writer, reader }
+    }
+
+    fn send(&mut self, line: &str) -> String {
+        writeln!(self.writer, "{}", line).unwrap();
+        let mut reply = String::new();
+        self.reader.read_line(&mut reply).unwrap();
+        reply
+    }
+
+    /// reports go on until an empty line
+    fn report(&mut self, line: &str) -> String {
+        let mut report = self.send(line);
+        loop {
+            let mut row = String::new();
+            self.reader.read_line(&mut row).unwrap();
+            if row == "\n" {
+                return report;
+            }
+            report += &row;
+        }
+    }
+}
+
+#[test]
+fn concurrent_connections() {
+    let address = start(server::serve);
+    let clients = (1..=4)
+        .map(|client| {
+            thread::spawn(move || {
+                let mut connection = Connection::open(address);
+                for tx in 0..50 {
+                    let tx = client * 100 + tx;
+                    let reply = connection.send(&format!("deposit,{},{},1", client, tx));
+                    assert_eq!(reply, "applied\n");
+                }
+                let reply =
+                    connection.send(&format!("withdrawal,{},{},5", client, client * 100 + 50));
+                assert_eq!(reply, "applied\n");
+            })
+        })
+        .collect::<Vec<_>>();
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    let mut connection = Connection::open(address);
+    assert_eq!(
+        connection.send("dispute,2,100"),
+        "rejected transaction 100 belongs to another client\n"
+    );
+    assert_eq!(
+        connection.send("transfer,1,1,1"),
+        "rejected unrecognized transaction type \"transfer\"\n"
+    );
+    assert_eq!(
+        connection.report("BALANCE 3"),
+        "client,available,held,total,locked\n3,45,0,45,false\n"
+    );
+    assert_eq!(
+        connection.report("DUMP"),
+        r#"client,available,held,total,locked
+1,45,0,45,false
+2,45,0,45,false
+3,45,0,45,false
+4,45,0,45,false
+"#
+    );
+}